@@ -1,16 +1,52 @@
 use std::env;
-use std::fs::read_to_string;
+use std::fs::{read_to_string, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::sync::{mpsc::sync_channel, Mutex};
+use std::thread;
+use std::time::Instant;
 
-fn solution1(filename: &str) -> u32 {
+const SEARCH_VALUES: [&str; 19] = [
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "one", "two", "three", "four", "five", "six",
+    "seven", "eight", "nine",
+];
+
+const DIGIT_WORDS: [&str; 9] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+// Lines are handed to the workers in chunks of roughly this many bytes.
+// Memory use is bounded by this times the channel capacity.
+const CHUNK_BYTES: usize = 1 << 20;
+
+const COLOUR_FIRST: &str = "\x1b[1;32m";
+const COLOUR_LAST: &str = "\x1b[1;34m";
+const COLOUR_BOTH: &str = "\x1b[1;33m";
+const COLOUR_RESET: &str = "\x1b[0m";
+
+#[derive(Debug)]
+struct Match<'a> {
+    start: usize,
+    text: &'a str,
+    value: u32,
+}
+
+impl Match<'_> {
+    fn contains(&self, index: usize) -> bool {
+        index >= self.start && index < self.start + self.text.len()
+    }
+}
+
+fn solution1(filename: &str) -> u64 {
     let lines = read_to_string(filename).unwrap();
     let lines = lines.trim().lines();
 
     let numbers = lines.map(|l| {
         let mut digits = l.chars().filter(|c| c.is_ascii_digit());
         let first = digits.next().unwrap();
-        let last = digits.last().unwrap_or(first);
+        let last = digits.next_back().unwrap_or(first);
         let number = format!("{}{}", first, last);
-        let number: u32 = number.parse().unwrap();
+        let number: u64 = number.parse().unwrap();
         number
     });
 
@@ -41,60 +77,322 @@ fn parse_number(value: &str) -> Option<u32> {
     }
 }
 
-fn solution2(filename: &str) -> u32 {
-    let search_values = vec![
-        "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "one", "two", "three", "four", "five",
-        "six", "seven", "eight", "nine",
-    ];
+fn part1_matches(line: &str) -> Vec<Match<'_>> {
+    line.char_indices()
+        .filter(|(_, c)| c.is_ascii_digit())
+        .map(|(i, c)| Match {
+            start: i,
+            text: &line[i..i + 1],
+            value: c.to_digit(10).unwrap(),
+        })
+        .collect()
+}
+
+fn part2_matches(line: &str) -> Vec<Match<'_>> {
+    let mut window_start = 0;
+    let mut window_end = 0;
+    let mut matches: Vec<Match> = Vec::new();
 
+    while window_end < line.len() {
+        let current_str = &line[window_start..=window_end];
+        // Is the string already in the number list?
+        if SEARCH_VALUES.contains(&current_str) {
+            let value = parse_number(current_str).unwrap();
+            matches.push(Match {
+                start: window_start,
+                text: current_str,
+                value,
+            });
+            window_start += 1;
+        } else {
+            // Are we building towards a number in the list?
+            if SEARCH_VALUES.iter().any(|n| n.starts_with(current_str)) {
+                window_end += 1;
+            } else {
+                window_start += 1;
+            }
+        }
+
+        if window_start > window_end {
+            window_end = window_start;
+        }
+    }
+
+    matches
+}
+
+fn calibration_value(matches: &[Match]) -> Option<u32> {
+    let first = matches.first()?;
+    let last = matches.last()?;
+    Some(first.value * 10 + last.value)
+}
+
+fn solution2(filename: &str) -> u64 {
     let lines = read_to_string(filename).unwrap();
     let lines = lines.trim().lines();
 
     let numbers = lines.map(|l| {
         // Find all numbers in the line.
+        let matches = part2_matches(l);
 
-        let mut window_start = 0;
-        let mut window_end = 0;
-        let mut numbers: Vec<u32> = Vec::new();
+        let first = matches.first().unwrap().value;
+        let last = matches.last().unwrap().value;
+        let number = format!("{}{}", first, last);
+        let number: u64 = number.parse().unwrap();
+        number
+    });
 
-        while window_end < l.len() {
-            let current_str = &l[window_start..=window_end];
-            // Is the string already in the number list?
-            if search_values.contains(&current_str) {
-                let number = parse_number(current_str).unwrap();
-                numbers.push(number);
-                window_start += 1;
-            } else {
-                // Are we building towards a number in the list?
-                if search_values.iter().any(|n| n.starts_with(current_str)) {
-                    window_end += 1;
-                } else {
-                    window_start += 1;
+    numbers.sum()
+}
+
+fn line_value1(line: &[u8]) -> Option<u32> {
+    let first = line.iter().find(|b| b.is_ascii_digit())?;
+    let last = line.iter().rfind(|b| b.is_ascii_digit())?;
+    Some(u32::from(first - b'0') * 10 + u32::from(last - b'0'))
+}
+
+fn digit_at(line: &[u8], index: usize) -> Option<u32> {
+    let rest = &line[index..];
+    if rest[0].is_ascii_digit() {
+        return Some(u32::from(rest[0] - b'0'));
+    }
+
+    DIGIT_WORDS
+        .iter()
+        .position(|w| rest.starts_with(w.as_bytes()))
+        .map(|i| i as u32 + 1)
+}
+
+fn line_value2(line: &[u8]) -> Option<u32> {
+    let first = (0..line.len()).find_map(|i| digit_at(line, i))?;
+    let last = (0..line.len()).rev().find_map(|i| digit_at(line, i))?;
+    Some(first * 10 + last)
+}
+
+fn chunk_total(chunk: &[u8], line_value: fn(&[u8]) -> Option<u32>) -> u64 {
+    chunk
+        .split(|b| *b == b'\n')
+        .map(|l| l.trim_ascii())
+        .filter(|l| !l.is_empty())
+        .map(|l| line_value(l).expect("line should contain a digit"))
+        .map(u64::from)
+        .sum()
+}
+
+/// Sum the calibration values of every line without holding the whole input in memory.
+/// The reader is split into chunks on line boundaries and summed on `threads` workers.
+fn solution_streaming<R: BufRead>(
+    mut reader: R,
+    line_value: fn(&[u8]) -> Option<u32>,
+    threads: usize,
+) -> u64 {
+    // With no workers nothing would ever take a chunk, and the first send would block forever.
+    assert!(threads > 0, "streaming needs at least one thread");
+    let (sender, receiver) = sync_channel::<Vec<u8>>(threads * 2);
+    let receiver = Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut total = 0;
+                    loop {
+                        let chunk = receiver.lock().unwrap().recv();
+                        match chunk {
+                            Ok(chunk) => total += chunk_total(&chunk, line_value),
+                            Err(_) => break,
+                        }
+                    }
+                    total
+                })
+            })
+            .collect();
+
+        let mut chunk: Vec<u8> = Vec::with_capacity(CHUNK_BYTES);
+        loop {
+            let read = reader
+                .read_until(b'\n', &mut chunk)
+                .expect("input should be readable");
+
+            if read == 0 || chunk.len() >= CHUNK_BYTES {
+                if !chunk.is_empty() {
+                    let full_chunk = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_BYTES));
+                    sender.send(full_chunk).unwrap();
+                }
+                if read == 0 {
+                    break;
                 }
             }
+        }
+        drop(sender);
+
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    })
+}
 
-            if window_start > window_end {
-                window_end = window_start;
+fn open_input(filename: &str) -> BufReader<File> {
+    let file = File::open(filename).expect("file should exist");
+    BufReader::new(file)
+}
+
+/// Write a random input of `line_count` lines, each containing at least one digit.
+fn generate(filename: &str, line_count: usize) {
+    let file = File::create(filename).expect("output file should be writable");
+    let mut writer = BufWriter::new(file);
+
+    // xorshift, so the generated file is the same on every run.
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+
+    for _ in 0..line_count {
+        let mut line = String::new();
+        let digit_position = next(6);
+
+        for i in 0..6 {
+            match next(3) {
+                _ if i == digit_position => line.push(char::from(b'0' + next(10) as u8)),
+                0 => line.push_str(DIGIT_WORDS[next(9) as usize]),
+                1 => line.push(char::from(b'0' + next(10) as u8)),
+                _ => line.push(char::from(b'a' + next(26) as u8)),
             }
         }
 
-        let first = numbers.first().unwrap();
-        let last = numbers.last().unwrap();
-        let number = format!("{}{}", first, last);
-        let number: u32 = number.parse().unwrap();
-        number
-    });
+        writeln!(writer, "{}", line).unwrap();
+    }
+}
 
-    numbers.sum()
+fn bench(filename: &str, threads: usize) {
+    let start = Instant::now();
+    let answer1 = solution1(filename);
+    let answer2 = solution2(filename);
+    let baseline = start.elapsed();
+
+    let start = Instant::now();
+    let streaming1 = solution_streaming(open_input(filename), line_value1, threads);
+    let streaming2 = solution_streaming(open_input(filename), line_value2, threads);
+    let streaming = start.elapsed();
+
+    assert_eq!(answer1, streaming1, "part 1 answers differ");
+    assert_eq!(answer2, streaming2, "part 2 answers differ");
+
+    println!("read_to_string: {:?}", baseline);
+    println!("streaming ({} threads): {:?}", threads, streaming);
+    println!(
+        "speedup: {:.2}x",
+        baseline.as_secs_f64() / streaming.as_secs_f64()
+    );
+}
+
+fn flag_value<'a>(flags: &'a [String], name: &str) -> Option<&'a str> {
+    let position = flags.iter().position(|f| f == name)?;
+    let value = flags.get(position + 1).expect("flag should have a value");
+    Some(value)
+}
+
+/// Render the line with the first and last match highlighted.
+/// Characters covered by both (overlapping words, or a single match) get a third colour.
+fn highlight(line: &str, matches: &[Match]) -> String {
+    let (first, last) = match (matches.first(), matches.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return line.to_string(),
+    };
+
+    let mut highlighted = String::new();
+    let mut current_colour: Option<&str> = None;
+
+    for (i, c) in line.char_indices() {
+        let colour = match (first.contains(i), last.contains(i)) {
+            (true, true) => Some(COLOUR_BOTH),
+            (true, false) => Some(COLOUR_FIRST),
+            (false, true) => Some(COLOUR_LAST),
+            (false, false) => None,
+        };
+
+        if colour != current_colour {
+            highlighted.push_str(colour.unwrap_or(COLOUR_RESET));
+            current_colour = colour;
+        }
+        highlighted.push(c);
+    }
+
+    if current_colour.is_some() {
+        highlighted.push_str(COLOUR_RESET);
+    }
+
+    highlighted
+}
+
+fn format_value(value: Option<u32>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+fn explain(filename: &str, diff_only: bool) {
+    let lines = read_to_string(filename).unwrap();
+    let lines = lines.trim().lines();
+
+    for (i, l) in lines.enumerate() {
+        let part1 = part1_matches(l);
+        let part2 = part2_matches(l);
+        let value1 = calibration_value(&part1);
+        let value2 = calibration_value(&part2);
+
+        if diff_only && value1 == value2 {
+            continue;
+        }
+
+        let marker = if value1 != value2 { " *" } else { "" };
+        println!("{}: {}{}", i + 1, l, marker);
+        println!(
+            "    part 1: {} -> {}",
+            highlight(l, &part1),
+            format_value(value1)
+        );
+        println!(
+            "    part 2: {} -> {}",
+            highlight(l, &part2),
+            format_value(value2)
+        );
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let filename = &args[1];
+    let flags = &args[2..];
 
-    let answer1 = solution1(filename);
+    if flags.iter().any(|f| f == "--explain") {
+        let diff_only = flags.iter().any(|f| f == "--diff-only");
+        explain(filename, diff_only);
+        return;
+    }
+
+    if let Some(line_count) = flag_value(flags, "--generate") {
+        let line_count = line_count.parse().expect("line count should be a number");
+        generate(filename, line_count);
+        return;
+    }
+
+    let threads = match flag_value(flags, "--threads") {
+        Some(threads) => threads
+            .parse::<NonZeroUsize>()
+            .expect("thread count should be a positive number")
+            .get(),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+
+    if flags.iter().any(|f| f == "--bench") {
+        bench(filename, threads);
+        return;
+    }
+
+    let answer1 = solution_streaming(open_input(filename), line_value1, threads);
     println!("{}", answer1);
 
-    let answer2 = solution2(filename);
+    let answer2 = solution_streaming(open_input(filename), line_value2, threads);
     println!("{}", answer2);
 }
@@ -1,13 +1,54 @@
-use std::{env, fs::read_to_string};
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, env, fmt, fs::read_to_string, process};
 
-#[derive(Debug)]
+// { colour: count }
+type CubeCounts = BTreeMap<String, u32>;
+
+// [(colour, count)] in the order they were written, so a turn can be written back out unchanged.
+type Draws = Vec<(String, u32)>;
+
+#[derive(Debug, PartialEq)]
 struct Turn {
-    red_count: u32,
-    green_count: u32,
-    blue_count: u32,
+    draws: Draws,
+}
+
+#[derive(Debug)]
+enum GameError {
+    EmptyTurn { game: u32, turn_index: usize },
+}
+
+#[derive(Debug)]
+struct Violation {
+    turn_index: usize,
+    colour: String,
+    excess: u32,
+}
+
+/// Frequencies of values grouped into buckets of `width`, keyed by each bucket's lower bound.
+#[derive(Debug)]
+struct Histogram {
+    width: u32,
+    buckets: BTreeMap<u32, u32>,
 }
 
+/// The first point at which drawing without replacement empties the bag of a colour.
 #[derive(Debug)]
+struct Exhaustion {
+    turn_index: usize,
+    colour: String,
+    shortfall: u32,
+}
+
+#[derive(Debug)]
+struct BagEstimate {
+    counts: CubeCounts,
+    // 95% interval on the number of cubes of each colour.
+    intervals: BTreeMap<String, (f64, f64)>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct Game {
     id: u32,
     turns: Vec<Turn>,
@@ -21,37 +62,337 @@ impl Game {
         }
     }
 
-    fn is_possible(&self, red_total: u32, green_total: u32, blue_total: u32) -> bool {
+    fn is_possible(&self, bag: &CubeCounts) -> bool {
+        self.turns.iter().all(|t| t.is_possible(bag))
+    }
+
+    /// Treat the turns as one sequence where drawn cubes are not returned to the bag.
+    fn check_sequential(&self, bag: &CubeCounts) -> Result<(), Exhaustion> {
+        let mut remaining = bag.clone();
+
+        for (turn_index, turn) in self.turns.iter().enumerate() {
+            for (colour, count) in turn.counts().iter() {
+                let available = remaining.entry(colour.clone()).or_insert(0);
+                if count > available {
+                    return Err(Exhaustion {
+                        turn_index,
+                        colour: colour.clone(),
+                        shortfall: count - *available,
+                    });
+                }
+                *available -= count;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn violations(&self, bag: &CubeCounts) -> Vec<Violation> {
         self.turns
             .iter()
-            .all(|t| t.is_possible(red_total, green_total, blue_total))
+            .enumerate()
+            .flat_map(|(turn_index, t)| {
+                t.excess(bag)
+                    .into_iter()
+                    .map(move |(colour, excess)| Violation {
+                        turn_index,
+                        colour,
+                        excess,
+                    })
+            })
+            .collect()
     }
 
-    fn power(&self) -> u32 {
-        let min_red = self.turns.iter().map(|t| t.red_count).max().unwrap_or(0);
-        let min_green = self.turns.iter().map(|t| t.green_count).max().unwrap_or(0);
-        let min_blue = self.turns.iter().map(|t| t.blue_count).max().unwrap_or(0);
+    /// Total cubes of each colour drawn over every turn.
+    fn drawn(&self) -> CubeCounts {
+        let mut drawn = CubeCounts::new();
+
+        for turn in self.turns.iter() {
+            for (colour, count) in turn.draws.iter() {
+                *drawn.entry(colour.clone()).or_insert(0) += count;
+            }
+        }
+
+        drawn
+    }
+
+    fn minimum_bag(&self) -> CubeCounts {
+        let mut bag = CubeCounts::new();
+
+        for turn in self.turns.iter() {
+            for (colour, count) in turn.counts().iter() {
+                let min_count = bag.entry(colour.clone()).or_insert(0);
+                *min_count = std::cmp::max(*min_count, *count);
+            }
+        }
 
-        min_red * min_blue * min_green
+        bag
+    }
+
+    fn power(&self) -> u32 {
+        let bag = self.minimum_bag();
+        if bag.is_empty() {
+            0
+        } else {
+            bag.values().product()
+        }
     }
 }
 
 impl Turn {
-    fn new() -> Self {
-        Self {
-            red_count: 0,
-            green_count: 0,
-            blue_count: 0,
+    fn new(draws: Draws) -> Self {
+        Self { draws }
+    }
+
+    /// How many cubes of each colour were drawn, adding up a colour written more than once.
+    fn counts(&self) -> CubeCounts {
+        let mut counts = CubeCounts::new();
+        for (colour, count) in self.draws.iter() {
+            *counts.entry(colour.clone()).or_insert(0) += count;
         }
+        counts
     }
 
-    fn is_possible(&self, red_total: u32, green_total: u32, blue_total: u32) -> bool {
-        self.red_count <= red_total
-            && self.green_count <= green_total
-            && self.blue_count <= blue_total
+    fn is_possible(&self, bag: &CubeCounts) -> bool {
+        // Colours missing from the bag have no cubes at all.
+        self.counts()
+            .iter()
+            .all(|(colour, count)| *count <= bag.get(colour).copied().unwrap_or(0))
+    }
+
+    /// Colours drawn in greater numbers than the bag holds, with how many cubes over they are.
+    fn excess(&self, bag: &CubeCounts) -> Vec<(String, u32)> {
+        self.counts()
+            .iter()
+            .filter_map(|(colour, count)| {
+                let available = bag.get(colour).copied().unwrap_or(0);
+                if *count > available {
+                    Some((colour.clone(), count - available))
+                } else {
+                    None
+                }
+            })
+            .collect()
     }
 }
 
+fn log_likelihood(drawn: &CubeCounts, proportions: &BTreeMap<String, f64>) -> f64 {
+    drawn
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(colour, count)| {
+            let p = proportions.get(colour).copied().unwrap_or(0.0);
+            f64::from(*count) * p.ln()
+        })
+        .sum()
+}
+
+fn proportions(counts: &CubeCounts) -> BTreeMap<String, f64> {
+    let total: u32 = counts.values().sum();
+    counts
+        .iter()
+        .map(|(colour, count)| (colour.clone(), f64::from(*count) / f64::from(total)))
+        .collect()
+}
+
+/// Wilson score interval for a proportion of `successes` out of `trials`.
+fn wilson_interval(successes: u32, trials: u32) -> (f64, f64) {
+    let z = 1.96;
+    let n = f64::from(trials);
+    let p = f64::from(successes) / n;
+
+    let denominator = 1.0 + z * z / n;
+    let centre = (p + z * z / (2.0 * n)) / denominator;
+    let half_width = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+
+    (
+        (centre - half_width).max(0.0),
+        (centre + half_width).min(1.0),
+    )
+}
+
+/// Maximum-likelihood bag of `bag_size` cubes, treating every turn as draws with replacement.
+/// Returns `None` if the bag is too small to hold one cube of each colour seen.
+fn estimate_bag(drawn: &CubeCounts, bag_size: u32) -> Option<BagEstimate> {
+    let trials: u32 = drawn.values().sum();
+    let seen: Vec<(&String, &u32)> = drawn.iter().filter(|(_, count)| **count > 0).collect();
+    if trials == 0 || (seen.len() as u32) > bag_size {
+        return None;
+    }
+
+    // Every colour seen needs at least one cube. The log-likelihood is separable and concave
+    // in each count, so handing out the remaining cubes greedily by marginal gain is optimal.
+    let mut counts: CubeCounts = seen.iter().map(|(c, _)| ((*c).clone(), 1)).collect();
+    for _ in seen.len() as u32..bag_size {
+        let (colour, _) = seen
+            .iter()
+            .map(|(colour, observed)| {
+                let n = f64::from(counts[*colour]);
+                let gain = f64::from(**observed) * ((n + 1.0) / n).ln();
+                (*colour, gain)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        *counts.get_mut(colour).unwrap() += 1;
+    }
+
+    let intervals = seen
+        .iter()
+        .map(|(colour, observed)| {
+            let (low, high) = wilson_interval(**observed, trials);
+            let size = f64::from(bag_size);
+            ((*colour).clone(), (low * size, high * size))
+        })
+        .collect();
+
+    Some(BagEstimate { counts, intervals })
+}
+
+/// Likelihood of the draws under `bag`, relative to the best possible bag (1.0).
+fn relative_likelihood(drawn: &CubeCounts, bag: &CubeCounts) -> f64 {
+    let best = log_likelihood(drawn, &proportions(drawn));
+    let hypothesis = log_likelihood(drawn, &proportions(bag));
+    (hypothesis - best).exp()
+}
+
+impl fmt::Display for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let draws: Vec<String> = self
+            .draws
+            .iter()
+            .map(|(colour, count)| format!("{} {}", count, colour))
+            .collect();
+        write!(f, "{}", draws.join(", "))
+    }
+}
+
+// A turn is a JSON object of colours to counts, with the keys kept in the order they were drawn.
+impl Serialize for Turn {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.draws.len()))?;
+        for (colour, count) in self.draws.iter() {
+            map.serialize_entry(colour, count)?;
+        }
+        map.end()
+    }
+}
+
+struct TurnVisitor;
+
+impl<'de> Visitor<'de> for TurnVisitor {
+    type Value = Turn;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of colours to counts")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Turn, A::Error> {
+        let mut draws = Draws::new();
+        while let Some((colour, count)) = access.next_entry::<String, u32>()? {
+            draws.push((colour, count));
+        }
+        Ok(Turn::new(draws))
+    }
+}
+
+impl<'de> Deserialize<'de> for Turn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TurnVisitor)
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::EmptyTurn { game, turn_index } => {
+                write!(f, "Game {} turn {} draws no cubes", game, turn_index + 1)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let turns: Vec<String> = self.turns.iter().map(|t| t.to_string()).collect();
+        write!(f, "Game {}: {}", self.id, turns.join("; "))
+    }
+}
+
+impl Histogram {
+    fn new(values: &[u32], width: u32) -> Self {
+        let mut buckets = BTreeMap::new();
+        for value in values {
+            *buckets.entry(value - value % width).or_insert(0) += 1;
+        }
+
+        Self { width, buckets }
+    }
+
+    /// Roughly `max_buckets` equal-width buckets over the range of `values`.
+    fn with_bucket_count(values: &[u32], max_buckets: u32) -> Self {
+        let max_value = values.iter().copied().max().unwrap_or(0);
+        let width = std::cmp::max(1, (max_value + max_buckets) / max_buckets);
+        Self::new(values, width)
+    }
+
+    fn bucket_label(&self, start: u32) -> String {
+        if self.width == 1 {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, start + self.width - 1)
+        }
+    }
+
+    fn render(&self, title: &str) {
+        const BAR_WIDTH: u32 = 40;
+        let largest = self.buckets.values().copied().max().unwrap_or(0);
+
+        println!("{}:", title);
+        for (start, frequency) in self.buckets.iter() {
+            let bar_length = (frequency * BAR_WIDTH).div_ceil(largest);
+            println!(
+                "{:>12} | {} {}",
+                self.bucket_label(*start),
+                "#".repeat(bar_length as usize),
+                frequency
+            );
+        }
+    }
+
+    fn print_csv(&self, statistic: &str) {
+        for (start, frequency) in self.buckets.iter() {
+            println!("{},{},{}", statistic, self.bucket_label(*start), frequency);
+        }
+    }
+}
+
+fn format_counts(counts: &CubeCounts) -> String {
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(colour, count)| format!("{} {}", count, colour))
+        .collect();
+    counts.join(", ")
+}
+
+fn parse_draws(line: &str) -> Draws {
+    let mut draws = Draws::new();
+
+    // An empty turn has nothing to split, which `validate` reports.
+    for substr in line.split(", ").filter(|s| !s.trim().is_empty()) {
+        let mut substr = substr.trim().split(" ");
+        let count: u32 = substr.next().unwrap().parse().unwrap();
+        let color = substr.next().unwrap();
+
+        draws.push((color.to_string(), count));
+    }
+
+    draws
+}
+
+fn parse_counts(line: &str) -> CubeCounts {
+    Turn::new(parse_draws(line)).counts()
+}
+
 fn parse_line(line: &str) -> Game {
     let mut split = line.split(":");
     let head = split.next().unwrap();
@@ -63,50 +404,249 @@ fn parse_line(line: &str) -> Game {
 
     let mut game = Game::new(id);
 
+    // A game without any turns is written with nothing after the colon.
+    if tail.trim().is_empty() {
+        return game;
+    }
+
     let turns = tail.split(";");
 
     for turn_line in turns {
-        let counts = turn_line.split(", ");
-        let mut turn = Turn::new();
-
-        for substr in counts {
-            let mut substr = substr.trim().split(" ");
-            let count: u32 = substr.next().unwrap().parse().unwrap();
-            let color = substr.next().unwrap();
-
-            match color {
-                "red" => turn.red_count = count,
-                "blue" => turn.blue_count = count,
-                "green" => turn.green_count = count,
-                _ => panic!("Unkown color {}", color),
-            }
-        }
-
+        let turn = Turn::new(parse_draws(turn_line));
         game.turns.push(turn);
     }
 
     game
 }
 
-fn solution1(games: &Vec<Game>) -> u32 {
-    let games = games.iter().filter(|g| g.is_possible(12, 13, 14));
+/// Check a game, however it was read, can be written back out as text.
+fn validate(game: &Game) -> Result<(), GameError> {
+    match game.turns.iter().position(|t| t.draws.is_empty()) {
+        Some(turn_index) => Err(GameError::EmptyTurn {
+            game: game.id,
+            turn_index,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn solution1(games: &[Game], bag: &CubeCounts) -> u32 {
+    let games = games.iter().filter(|g| g.is_possible(bag));
     games.map(|g| g.id).sum()
 }
 
-fn solution2(games: &Vec<Game>) -> u32 {
+fn report(games: &[Game], bag: &CubeCounts) {
+    println!("Bag: {}", format_counts(bag));
+
+    for game in games {
+        let violations = game.violations(bag);
+        if violations.is_empty() {
+            continue;
+        }
+
+        println!("Game {} is impossible:", game.id);
+        for violation in violations {
+            println!(
+                "    turn {}: {} exceeds the bag by {}",
+                violation.turn_index + 1,
+                violation.colour,
+                violation.excess
+            );
+        }
+    }
+
+    // The smallest bag that covers every game's own minimum bag.
+    let mut minimal_bag = CubeCounts::new();
+    for game in games {
+        for (colour, count) in game.minimum_bag() {
+            let min_count = minimal_bag.entry(colour).or_insert(0);
+            *min_count = std::cmp::max(*min_count, count);
+        }
+    }
+    println!("Minimal bag for all games: {}", format_counts(&minimal_bag));
+}
+
+fn sequential(games: &[Game], bag: &CubeCounts) {
+    println!("Bag: {}", format_counts(bag));
+
+    let mut possible_sum = 0;
+    for game in games {
+        match game.check_sequential(bag) {
+            Ok(()) => possible_sum += game.id,
+            Err(exhaustion) => println!(
+                "Game {}: bag runs out of {} at turn {}, {} short",
+                game.id,
+                exhaustion.colour,
+                exhaustion.turn_index + 1,
+                exhaustion.shortfall
+            ),
+        }
+    }
+
+    println!("Sum of possible game IDs: {}", possible_sum);
+}
+
+fn stats(games: &[Game], csv: bool) {
+    let turns: Vec<&Turn> = games.iter().flat_map(|g| g.turns.iter()).collect();
+    let turn_counts: Vec<CubeCounts> = turns.iter().map(|t| t.counts()).collect();
+    let mut colours: Vec<&String> = turn_counts.iter().flat_map(|c| c.keys()).collect();
+    colours.sort();
+    colours.dedup();
+
+    if csv {
+        println!("statistic,key,value");
+    }
+
+    for colour in colours {
+        // Turns that didn't draw the colour count as drawing zero of it.
+        let counts: Vec<u32> = turn_counts
+            .iter()
+            .map(|c| c.get(colour).copied().unwrap_or(0))
+            .collect();
+        let max = counts.iter().max().unwrap();
+        let mean = f64::from(counts.iter().sum::<u32>()) / counts.len() as f64;
+        let histogram = Histogram::new(&counts, 1);
+
+        if csv {
+            println!("max,{},{}", colour, max);
+            println!("mean,{},{:.3}", colour, mean);
+            histogram.print_csv(&format!("{} per turn", colour));
+        } else {
+            println!("{}: max {}, mean {:.3}", colour, max, mean);
+            histogram.render(&format!("{} per turn", colour));
+            println!();
+        }
+    }
+
+    let turn_counts: Vec<u32> = games.iter().map(|g| g.turns.len() as u32).collect();
+    let turn_histogram = Histogram::new(&turn_counts, 1);
+
+    let powers: Vec<u32> = games.iter().map(|g| g.power()).collect();
+    let power_histogram = Histogram::with_bucket_count(&powers, 10);
+
+    if csv {
+        turn_histogram.print_csv("turns per game");
+        power_histogram.print_csv("power");
+    } else {
+        turn_histogram.render("Turns per game");
+        println!();
+        power_histogram.render("Power");
+    }
+}
+
+fn print_inference(label: &str, drawn: &CubeCounts, bag: &CubeCounts, bag_size: u32) {
+    println!("{}: drew {}", label, format_counts(drawn));
+
+    match estimate_bag(drawn, bag_size) {
+        Some(estimate) => {
+            let estimates: Vec<String> = estimate
+                .counts
+                .iter()
+                .map(|(colour, count)| {
+                    let (low, high) = estimate.intervals[colour];
+                    format!("{} {} [{:.1}, {:.1}]", count, colour, low, high)
+                })
+                .collect();
+            println!("    estimate: {}", estimates.join(", "));
+        }
+        None => println!("    estimate: none with {} cubes", bag_size),
+    }
+
+    println!(
+        "    {}: relative likelihood {:.4}",
+        format_counts(bag),
+        relative_likelihood(drawn, bag)
+    );
+}
+
+fn infer(games: &[Game], bag: &CubeCounts, bag_size: u32) {
+    println!("Bag size: {}", bag_size);
+
+    let mut all_drawn = CubeCounts::new();
+    for game in games {
+        let drawn = game.drawn();
+        for (colour, count) in drawn.iter() {
+            *all_drawn.entry(colour.clone()).or_insert(0) += count;
+        }
+
+        print_inference(&format!("Game {}", game.id), &drawn, bag, bag_size);
+    }
+
+    print_inference("All games", &all_drawn, bag, bag_size);
+}
+
+fn solution2(games: &[Game]) -> u32 {
     games.iter().map(|g| g.power()).sum()
 }
 
 fn main() {
     let mut args = env::args();
     let filename = args.nth(1).expect("Filename must be given.");
+    let flags: Vec<String> = args.collect();
+
+    let input = read_to_string(&filename).unwrap();
+    let games: Vec<Game> = if filename.ends_with(".json") {
+        serde_json::from_str(&input).expect("JSON input should be a list of games")
+    } else {
+        let input = input.trim().lines();
+        input.map(parse_line).collect()
+    };
+
+    let errors: Vec<GameError> = games.iter().filter_map(|g| validate(g).err()).collect();
+    for e in errors.iter() {
+        eprintln!("{}", e);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+
+    if flags.iter().any(|f| f == "--to-json") {
+        println!("{}", serde_json::to_string_pretty(&games).unwrap());
+        return;
+    }
 
-    let input = read_to_string(filename).unwrap();
-    let input = input.trim().lines();
-    let games = input.map(|l| parse_line(l));
-    let games: Vec<Game> = games.collect();
+    if flags.iter().any(|f| f == "--to-text") {
+        for game in games.iter() {
+            println!("{}", game);
+        }
+        return;
+    }
+
+    let bag = match flags.iter().position(|f| f == "--bag") {
+        Some(i) => parse_counts(flags.get(i + 1).expect("--bag should have a value")),
+        None => parse_counts("12 red, 13 green, 14 blue"),
+    };
+
+    if flags.iter().any(|f| f == "stats") {
+        let csv = flags.iter().any(|f| f == "--csv");
+        stats(&games, csv);
+        return;
+    }
+
+    if flags.iter().any(|f| f == "--report") {
+        report(&games, &bag);
+        return;
+    }
+
+    if flags.iter().any(|f| f == "--sequential") {
+        sequential(&games, &bag);
+        return;
+    }
+
+    if flags.iter().any(|f| f == "--infer") {
+        let bag_size = match flags.iter().position(|f| f == "--bag-size") {
+            Some(i) => flags
+                .get(i + 1)
+                .expect("--bag-size should have a value")
+                .parse()
+                .expect("bag size should be a number"),
+            None => bag.values().sum(),
+        };
+        infer(&games, &bag, bag_size);
+        return;
+    }
 
-    let answer1 = solution1(&games);
+    let answer1 = solution1(&games, &bag);
     println!("Solution1: {}", answer1);
 
     let answer2 = solution2(&games);
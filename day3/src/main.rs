@@ -1,286 +1,1535 @@
-use std::{collections::HashSet, env, fs::read_to_string};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    env, fmt,
+    fs::{read_to_string, File},
+    io::{self, BufWriter, Write},
+    mem::size_of,
+    process,
+    time::Instant,
+};
 
 #[derive(Debug)]
 enum SchematicValue {
     Blank,
-    NumberStart(u32, usize),
-    NumberContinuation(u32, Position),
-    Gear,
-    Symbol,
+    NumberStart(u64, usize),
+    NumberContinuation(Position),
+    Symbol(char),
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Sum,
+    Product,
+    Max,
+    Count,
+}
+
+/// How many distinct part numbers a symbol must touch for its rule to apply.
+#[derive(Debug, Clone, Copy)]
+enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SymbolRule {
+    operation: Operation,
+    arity: Arity,
+}
+
+// { symbol: rule }
+type RuleTable = HashMap<char, SymbolRule>;
+
+/// What to do with rows that are a different width to the first row.
+#[derive(Debug, Clone, Copy)]
+enum RaggedRows {
+    Error,
+    Pad,
+}
+
+#[derive(Debug)]
+enum ParseError {
+    RaggedRow {
+        row: usize,
+        width: usize,
+        expected: usize,
+    },
+    NumberOverflow {
+        position: Position,
+    },
+}
+
+/// Adding or multiplying part numbers overflowed while evaluating the item at `position`.
+#[derive(Debug)]
+struct OverflowError {
+    position: Position,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 struct Position {
     row: usize,
     column: usize,
 }
 
+#[derive(Debug, Clone, Copy)]
+enum NeighbourhoodShape {
+    // Directly above, below, left and right.
+    Orthogonal,
+    // Every cell within the given distance, diagonals included.
+    Chebyshev(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Neighbourhood {
+    shape: NeighbourhoodShape,
+    // Wrap around the edges of the schematic, as if it were a torus.
+    wrap: bool,
+}
+
+/// A number or symbol found while scanning a line, located by column.
+#[derive(Debug)]
+enum Token {
+    Number {
+        column: usize,
+        width: usize,
+        value: u64,
+    },
+    Symbol {
+        column: usize,
+        symbol: char,
+    },
+}
+
 #[derive(Debug)]
 struct Schematic {
     matrix: Vec<Vec<SchematicValue>>,
 }
 
-impl Position {
-    fn left(&self, _schematic: &Schematic) -> Option<Self> {
-        if self.column > 0 {
-            Some(Self {
-                row: self.row,
-                column: self.column - 1,
-            })
-        } else {
-            None
+#[derive(Debug)]
+struct NumberSpan {
+    column: usize,
+    width: usize,
+    value: u64,
+}
+
+/// Stores only the numbers and symbols, for large schematics that are mostly blank.
+#[derive(Debug)]
+struct SparseSchematic {
+    row_widths: Vec<usize>,
+    // Numbers in each row, in column order.
+    numbers: Vec<Vec<NumberSpan>>,
+    // { position: symbol }
+    symbols: HashMap<Position, char>,
+}
+
+/// Read access shared by the dense and sparse schematics.
+trait Grid {
+    fn height(&self) -> usize;
+
+    fn row_width(&self, row: usize) -> usize;
+
+    /// The start of the number covering `position`, if there is one.
+    fn number_start(&self, position: &Position) -> Option<Position>;
+
+    fn symbol_at(&self, position: &Position) -> Option<char>;
+
+    /// The value of the number starting at `position`.
+    fn get_number(&self, position: &Position) -> u64;
+
+    /// The start position, value and width of every number.
+    fn numbers(&self) -> Vec<(Position, u64, usize)>;
+
+    fn symbols(&self) -> Vec<(Position, char)>;
+
+    fn is_symbol(&self, position: &Position) -> bool {
+        self.symbol_at(position).is_some()
+    }
+
+    /// Cells in the neighbourhood of the horizontal span of `width` cells from `start_position`,
+    /// not including the span itself.
+    fn get_neighbours(
+        &self,
+        start_position: &Position,
+        width: usize,
+        neighbourhood: &Neighbourhood,
+    ) -> Vec<Position>
+    where
+        Self: Sized,
+    {
+        let offsets = neighbourhood.shape.offsets();
+        let span = start_position.column..start_position.column + width;
+
+        let mut neighbours: HashSet<Position> = HashSet::new();
+
+        for column in span.clone() {
+            let pos = Position {
+                row: start_position.row,
+                column,
+            };
+
+            let cell_neighbours = offsets
+                .iter()
+                .filter_map(|(r, c)| pos.offset(self, *r, *c, neighbourhood.wrap))
+                .filter(|p| !(p.row == start_position.row && span.contains(&p.column)));
+            neighbours.extend(cell_neighbours);
         }
+
+        neighbours.into_iter().collect()
     }
+}
 
-    fn right(&self, schematic: &Schematic) -> Option<Self> {
-        if self.column < schematic.width() - 1 {
-            Some(Self {
-                row: self.row,
-                column: self.column + 1,
-            })
+/// A schematic that keeps its part numbers and rule values up to date as it is edited.
+#[derive(Debug)]
+struct LiveSchematic {
+    schematic: Schematic,
+    neighbourhood: Neighbourhood,
+    rules: RuleTable,
+    // { number start: value } for every part number.
+    part_numbers: HashMap<Position, u64>,
+    // { symbol position: value } for every symbol whose rule applies.
+    rule_values: HashMap<Position, u64>,
+}
+
+#[derive(Debug)]
+enum Edit {
+    Set(Position, char),
+    Insert(Position, char),
+    Delete(Position),
+}
+
+#[derive(Debug)]
+enum EditError {
+    OutOfBounds(Position),
+    Parse(ParseError),
+    Overflow(OverflowError),
+}
+
+/// What a single cell of the schematic holds, and what it touches.
+#[derive(Debug)]
+enum PointQuery {
+    Blank,
+    Number {
+        start: Position,
+        value: u64,
+        // Symbols touching any digit of the number, so it's a part number if this isn't empty.
+        symbols: Vec<(Position, char)>,
+    },
+    Symbol {
+        symbol: char,
+        numbers: Vec<(Position, u64)>,
+    },
+}
+
+/// Numbers and symbols are keyed by their position; numbers by the position of their first digit.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
+enum GraphNode {
+    Number(Position),
+    Symbol(Position),
+}
+
+/// Bipartite graph joining each number to the symbols adjacent to it.
+#[derive(Debug)]
+struct PartGraph {
+    // { node: neighbouring nodes }
+    adjacency: BTreeMap<GraphNode, BTreeSet<GraphNode>>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonNode {
+    id: String,
+    kind: &'static str,
+    row: usize,
+    column: usize,
+    label: String,
+    degree: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonGraph {
+    nodes: Vec<JsonNode>,
+    edges: Vec<(String, String)>,
+    components: Vec<Vec<String>>,
+}
+
+impl Position {
+    /// The position `row_offset` rows and `column_offset` columns away, if it is in the schematic.
+    fn offset(
+        &self,
+        schematic: &impl Grid,
+        row_offset: isize,
+        column_offset: isize,
+        wrap: bool,
+    ) -> Option<Self> {
+        let height = schematic.height() as isize;
+        let row = self.row as isize + row_offset;
+        let row = if wrap { row.rem_euclid(height) } else { row };
+        if row < 0 || row >= height {
+            return None;
+        }
+
+        let width = schematic.row_width(row as usize) as isize;
+        let column = self.column as isize + column_offset;
+        let column = if wrap && width > 0 {
+            column.rem_euclid(width)
         } else {
-            None
+            column
+        };
+        if column < 0 || column >= width {
+            return None;
         }
+
+        Some(Self {
+            row: row as usize,
+            column: column as usize,
+        })
     }
+}
 
-    fn up(&self, _schematic: &Schematic) -> Option<Self> {
-        if self.row > 0 {
-            Some(Self {
-                row: self.row - 1,
-                column: self.column,
-            })
-        } else {
-            None
+impl NeighbourhoodShape {
+    fn offsets(&self) -> Vec<(isize, isize)> {
+        match self {
+            NeighbourhoodShape::Orthogonal => vec![(-1, 0), (1, 0), (0, -1), (0, 1)],
+            NeighbourhoodShape::Chebyshev(radius) => {
+                let radius = *radius as isize;
+                let range = -radius..=radius;
+                range
+                    .clone()
+                    .flat_map(|r| range.clone().map(move |c| (r, c)))
+                    .filter(|offset| offset != &(0, 0))
+                    .collect()
+            }
         }
     }
 
-    fn down(&self, schematic: &Schematic) -> Option<Self> {
-        if self.row < schematic.height() - 1 {
-            Some(Self {
-                row: self.row + 1,
-                column: self.column,
-            })
-        } else {
-            None
+    /// How many rows away a neighbour can be.
+    fn radius(&self) -> usize {
+        match self {
+            NeighbourhoodShape::Orthogonal => 1,
+            NeighbourhoodShape::Chebyshev(radius) => *radius,
         }
     }
 }
 
-impl Schematic {
-    fn width(&self) -> usize {
-        self.matrix[0].len()
+impl Neighbourhood {
+    fn parse(shape: &str, wrap: bool) -> Self {
+        let shape = match shape {
+            "4" => NeighbourhoodShape::Orthogonal,
+            "8" => NeighbourhoodShape::Chebyshev(1),
+            _ => {
+                let radius = shape
+                    .strip_prefix("chebyshev:")
+                    .expect("neighbourhood should be 4, 8 or chebyshev:N");
+                NeighbourhoodShape::Chebyshev(radius.parse().expect("radius should be a number"))
+            }
+        };
+
+        Self { shape, wrap }
     }
+}
 
+impl Grid for Schematic {
     fn height(&self) -> usize {
         self.matrix.len()
     }
 
-    fn get_neighbours(&self, start_position: &Position, width: usize) -> Vec<Position> {
-        let mut neighbours: Vec<Option<Position>> = Vec::new();
+    fn row_width(&self, row: usize) -> usize {
+        self.matrix[row].len()
+    }
 
-        // Add the left edge.
-        let left = start_position.left(self);
-        if let Some(left) = left {
-            neighbours.push(left.up(self));
-            neighbours.push(left.down(self));
-            neighbours.push(Some(left));
+    fn number_start(&self, position: &Position) -> Option<Position> {
+        let value = &self.matrix[position.row][position.column];
+        match value {
+            SchematicValue::NumberContinuation(pos) => Some(pos.clone()),
+            SchematicValue::NumberStart(_, _) => Some(position.clone()),
+            _ => None,
         }
+    }
 
-        // Add top/bottom for all middle cells
-        for column_offset in 0..width {
-            let pos = Position {
-                row: start_position.row,
-                column: start_position.column + column_offset,
-            };
-            neighbours.push(pos.up(self));
-            neighbours.push(pos.down(self));
+    fn symbol_at(&self, position: &Position) -> Option<char> {
+        match self.matrix[position.row][position.column] {
+            SchematicValue::Symbol(c) => Some(c),
+            _ => None,
         }
+    }
 
-        // Add the right edge.
-        let end_position = Position {
-            row: start_position.row,
-            column: start_position.column + width - 1,
-        };
-        let right = end_position.right(self);
-        if let Some(right) = right {
-            neighbours.push(right.up(self));
-            neighbours.push(right.down(self));
-            neighbours.push(Some(right));
+    fn get_number(&self, position: &Position) -> u64 {
+        let value = &self.matrix[position.row][position.column];
+        match value {
+            SchematicValue::NumberStart(n, _) => *n,
+            _ => panic!(
+                "Value at position ({}, {}) is not a number.",
+                position.row, position.column
+            ),
         }
+    }
 
-        let valid_neightbours = neighbours.into_iter().flatten();
+    fn numbers(&self) -> Vec<(Position, u64, usize)> {
+        (0..self.height())
+            .flat_map(|i| self.numbers_in_row(i))
+            .collect()
+    }
 
-        valid_neightbours.collect()
+    fn symbols(&self) -> Vec<(Position, char)> {
+        (0..self.height())
+            .flat_map(|i| self.symbols_in_row(i))
+            .collect()
     }
+}
 
-    fn is_symbol(&self, position: &Position) -> bool {
-        let value = &self.matrix[position.row][position.column];
-        matches!(value, SchematicValue::Symbol | SchematicValue::Gear)
+impl Grid for SparseSchematic {
+    fn height(&self) -> usize {
+        self.row_widths.len()
     }
 
-    fn get_number(&self, position: &Position) -> u32 {
-        let value = &self.matrix[position.row][position.column];
-        match value {
-            SchematicValue::NumberStart(n, _) => *n,
+    fn row_width(&self, row: usize) -> usize {
+        self.row_widths[row]
+    }
+
+    fn number_start(&self, position: &Position) -> Option<Position> {
+        let span = self.span_covering(position)?;
+        Some(Position {
+            row: position.row,
+            column: span.column,
+        })
+    }
+
+    fn symbol_at(&self, position: &Position) -> Option<char> {
+        self.symbols.get(position).copied()
+    }
+
+    fn get_number(&self, position: &Position) -> u64 {
+        match self.span_covering(position) {
+            Some(span) if span.column == position.column => span.value,
             _ => panic!(
                 "Value at position ({}, {}) is not a number.",
                 position.row, position.column
             ),
         }
     }
+
+    fn numbers(&self) -> Vec<(Position, u64, usize)> {
+        let row_iter = self.numbers.iter().enumerate();
+        row_iter
+            .flat_map(|(i, row)| {
+                row.iter().map(move |span| {
+                    let pos = Position {
+                        row: i,
+                        column: span.column,
+                    };
+                    (pos, span.value, span.width)
+                })
+            })
+            .collect()
+    }
+
+    fn symbols(&self) -> Vec<(Position, char)> {
+        self.symbols.iter().map(|(p, c)| (p.clone(), *c)).collect()
+    }
 }
 
-fn parse_file(file: &str) -> Schematic {
-    let lines = file.trim().lines();
+impl SparseSchematic {
+    fn span_covering(&self, position: &Position) -> Option<&NumberSpan> {
+        let row = &self.numbers[position.row];
+        // Spans are sorted and don't overlap, so only the last one starting at or before the
+        // column can cover it.
+        let index = row.partition_point(|span| span.column <= position.column);
+        let span = row.get(index.checked_sub(1)?)?;
+        if position.column < span.column + span.width {
+            Some(span)
+        } else {
+            None
+        }
+    }
 
-    let mut matrix: Vec<Vec<SchematicValue>> = Vec::new();
+    /// Approximate heap memory used, in bytes.
+    fn heap_size(&self) -> usize {
+        let row_widths = self.row_widths.capacity() * size_of::<usize>();
+        let number_rows = self.numbers.capacity() * size_of::<Vec<NumberSpan>>();
+        let numbers: usize = self
+            .numbers
+            .iter()
+            .map(|row| row.capacity() * size_of::<NumberSpan>())
+            .sum();
+        // Each hash map slot also has a control byte.
+        let symbols = self.symbols.capacity() * (size_of::<(Position, char)>() + 1);
 
-    for (i, line) in lines.into_iter().enumerate() {
-        let mut pos = 0;
-        let chars: Vec<char> = line.chars().collect();
-        let mut line_values: Vec<SchematicValue> = Vec::new();
-
-        while pos < chars.len() {
-            let next_char = chars[pos];
-
-            if next_char.is_ascii_digit() {
-                let mut value: u32 = next_char.to_string().parse().unwrap();
-                let start_pos = pos;
-                pos += 1;
-
-                // Inner loop to consume the rest of the multi-digit number.
-                while pos < chars.len() {
-                    let next_char = chars[pos];
-                    if next_char.is_ascii_digit() {
-                        value *= 10;
-                        value += next_char.to_string().parse::<u32>().unwrap();
-                        pos += 1;
-                    } else {
-                        break;
-                    }
+        row_widths + number_rows + numbers + symbols
+    }
+}
+
+impl Schematic {
+    fn query(&self, position: &Position, neighbourhood: &Neighbourhood) -> Option<PointQuery> {
+        let value = self.matrix.get(position.row)?.get(position.column)?;
+
+        let query = match value {
+            SchematicValue::Blank => PointQuery::Blank,
+            SchematicValue::NumberStart(_, _) | SchematicValue::NumberContinuation(_) => {
+                let start = self.number_start(position).unwrap();
+                let (value, width) = match self.matrix[start.row][start.column] {
+                    SchematicValue::NumberStart(n, l) => (n, l),
+                    _ => unreachable!(),
+                };
+
+                let mut symbols: Vec<(Position, char)> = self
+                    .get_neighbours(&start, width, neighbourhood)
+                    .into_iter()
+                    .filter_map(|p| match self.matrix[p.row][p.column] {
+                        SchematicValue::Symbol(c) => Some((p, c)),
+                        _ => None,
+                    })
+                    .collect();
+                symbols.sort();
+
+                PointQuery::Number {
+                    start,
+                    value,
+                    symbols,
                 }
+            }
+            SchematicValue::Symbol(symbol) => {
+                let starts: BTreeSet<Position> = self
+                    .get_neighbours(position, 1, neighbourhood)
+                    .into_iter()
+                    .filter_map(|p| self.number_start(&p))
+                    .collect();
+                let numbers = starts
+                    .into_iter()
+                    .map(|p| {
+                        let number = self.get_number(&p);
+                        (p, number)
+                    })
+                    .collect();
 
-                let length = pos - start_pos;
-                line_values.push(SchematicValue::NumberStart(value, length));
-                for _ in 1..length {
-                    line_values.push(SchematicValue::NumberContinuation(
-                        value,
-                        Position {
-                            row: i,
-                            column: start_pos,
-                        },
-                    ));
+                PointQuery::Symbol {
+                    symbol: *symbol,
+                    numbers,
                 }
-            } else if next_char == '*' {
-                line_values.push(SchematicValue::Gear);
-                pos += 1;
-            } else if next_char == '.' {
-                line_values.push(SchematicValue::Blank);
-                pos += 1;
-            } else {
-                line_values.push(SchematicValue::Symbol);
-                pos += 1;
             }
+        };
+
+        Some(query)
+    }
+
+    fn numbers_in_row(&self, row: usize) -> Vec<(Position, u64, usize)> {
+        let col_iter = self.matrix[row].iter().enumerate();
+        col_iter
+            .filter_map(|(j, v)| match v {
+                SchematicValue::NumberStart(n, l) => Some((Position { row, column: j }, *n, *l)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn symbols_in_row(&self, row: usize) -> Vec<(Position, char)> {
+        let col_iter = self.matrix[row].iter().enumerate();
+        col_iter
+            .filter_map(|(j, v)| match v {
+                SchematicValue::Symbol(c) => Some((Position { row, column: j }, *c)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The characters of a row, as they would appear in the input.
+    fn row_chars(&self, row: usize) -> Vec<char> {
+        let mut chars: Vec<char> = Vec::with_capacity(self.row_width(row));
+
+        for value in self.matrix[row].iter() {
+            match value {
+                SchematicValue::Blank => chars.push('.'),
+                // Pad with zeros to the number's width so leading zeros survive.
+                SchematicValue::NumberStart(n, l) => {
+                    chars.extend(format!("{:0width$}", n, width = l).chars())
+                }
+                SchematicValue::NumberContinuation(_) => (),
+                SchematicValue::Symbol(c) => chars.push(*c),
+            }
+        }
+
+        chars
+    }
+
+    /// Replace a row, re-splitting it into numbers and symbols.
+    fn set_row(&mut self, row: usize, chars: &[char]) -> Result<(), ParseError> {
+        let line: String = chars.iter().collect();
+        let (tokens, line_width) = tokenize_line(&line, row)?;
+        self.matrix[row] = build_row(tokens, line_width, row);
+        Ok(())
+    }
+
+    /// Approximate heap memory used, in bytes.
+    fn heap_size(&self) -> usize {
+        let rows = self.matrix.capacity() * size_of::<Vec<SchematicValue>>();
+        let cells: usize = self
+            .matrix
+            .iter()
+            .map(|row| row.capacity() * size_of::<SchematicValue>())
+            .sum();
+
+        rows + cells
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::RaggedRow {
+                row,
+                width,
+                expected,
+            } => write!(f, "Row {} has width {}, expected {}.", row, width, expected),
+            ParseError::NumberOverflow { position } => write!(
+                f,
+                "Number at ({}, {}) is too large.",
+                position.row, position.column
+            ),
+        }
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::OutOfBounds(position) => write!(
+                f,
+                "({}, {}) is outside the schematic.",
+                position.row, position.column
+            ),
+            EditError::Parse(e) => write!(f, "{}", e),
+            EditError::Overflow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Overflow evaluating ({}, {}).",
+            self.position.row, self.position.column
+        )
+    }
+}
+
+fn tokenize_line(line: &str, row: usize) -> Result<(Vec<Token>, usize), ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let next_char = chars[pos];
+
+        if next_char.is_ascii_digit() {
+            let mut value: u64 = next_char.to_string().parse().unwrap();
+            let start_pos = pos;
+            pos += 1;
+
+            // Inner loop to consume the rest of the multi-digit number.
+            while pos < chars.len() {
+                let next_char = chars[pos];
+                if next_char.is_ascii_digit() {
+                    let digit = next_char.to_string().parse::<u64>().unwrap();
+                    value = value
+                        .checked_mul(10)
+                        .and_then(|v| v.checked_add(digit))
+                        .ok_or(ParseError::NumberOverflow {
+                            position: Position {
+                                row,
+                                column: start_pos,
+                            },
+                        })?;
+                    pos += 1;
+                } else {
+                    break;
+                }
+            }
+
+            tokens.push(Token::Number {
+                column: start_pos,
+                width: pos - start_pos,
+                value,
+            });
+        } else {
+            if next_char != '.' {
+                tokens.push(Token::Symbol {
+                    column: pos,
+                    symbol: next_char,
+                });
+            }
+            pos += 1;
         }
-        matrix.push(line_values);
     }
 
-    Schematic { matrix }
+    Ok((tokens, chars.len()))
 }
 
-fn solution1(schematic: &Schematic) -> u32 {
-    let row_iter = schematic.matrix.iter();
+/// The width each row should have once the ragged row policy has been applied.
+fn resolve_row_widths(widths: &[usize], ragged_rows: RaggedRows) -> Result<Vec<usize>, ParseError> {
+    let expected = widths.first().copied().unwrap_or(0);
+    let max_width = widths.iter().copied().max().unwrap_or(0);
 
-    row_iter
-        .enumerate()
-        .flat_map(|(i, row)| {
-            let number_iter = row
-                .iter()
-                .enumerate()
-                .map(|(j, v)| match v {
-                    SchematicValue::NumberStart(n, l) => (j, Some((n, l))),
-                    _ => (j, None),
-                })
-                .filter(|(_j, v)| v.is_some())
-                .map(|(j, v)| (j, v.unwrap()));
-
-            let col_iter = number_iter
-                .filter(|(j, (_val, width))| {
-                    let pos = Position { row: i, column: *j };
-                    let neighbours = schematic.get_neighbours(&pos, **width);
-                    let mut neighbours = neighbours.iter();
-                    neighbours.any(|p| schematic.is_symbol(p))
-                })
-                .map(|(_j, value)| value.0);
-            let col_vec: Vec<&u32> = col_iter.collect();
-            col_vec
+    match ragged_rows {
+        RaggedRows::Error => {
+            let ragged = widths.iter().position(|w| *w != expected);
+            match ragged {
+                Some(row) => Err(ParseError::RaggedRow {
+                    row,
+                    width: widths[row],
+                    expected,
+                }),
+                None => Ok(widths.to_vec()),
+            }
+        }
+        RaggedRows::Pad => Ok(vec![max_width; widths.len()]),
+    }
+}
+
+fn input_lines(file: &str) -> impl Iterator<Item = &str> {
+    file.trim().lines().map(|l| l.trim_end_matches('\r'))
+}
+
+fn build_row(tokens: Vec<Token>, line_width: usize, row: usize) -> Vec<SchematicValue> {
+    let mut line_values: Vec<SchematicValue> = Vec::with_capacity(line_width);
+
+    for token in tokens {
+        let column = match token {
+            Token::Number { column, .. } | Token::Symbol { column, .. } => column,
+        };
+        line_values.resize_with(column, || SchematicValue::Blank);
+
+        match token {
+            Token::Number { width, value, .. } => {
+                line_values.push(SchematicValue::NumberStart(value, width));
+                for _ in 1..width {
+                    line_values.push(SchematicValue::NumberContinuation(Position { row, column }));
+                }
+            }
+            Token::Symbol { symbol, .. } => line_values.push(SchematicValue::Symbol(symbol)),
+        }
+    }
+
+    line_values.resize_with(line_width, || SchematicValue::Blank);
+    line_values
+}
+
+fn parse_file(file: &str, ragged_rows: RaggedRows) -> Result<Schematic, ParseError> {
+    let mut matrix: Vec<Vec<SchematicValue>> = Vec::new();
+    let mut widths: Vec<usize> = Vec::new();
+
+    for (i, line) in input_lines(file).enumerate() {
+        let (tokens, line_width) = tokenize_line(line, i)?;
+        matrix.push(build_row(tokens, line_width, i));
+        widths.push(line_width);
+    }
+
+    let widths = resolve_row_widths(&widths, ragged_rows)?;
+    for (row, width) in matrix.iter_mut().zip(widths) {
+        row.resize_with(width, || SchematicValue::Blank);
+    }
+
+    Ok(Schematic { matrix })
+}
+
+fn parse_file_sparse(file: &str, ragged_rows: RaggedRows) -> Result<SparseSchematic, ParseError> {
+    let mut widths: Vec<usize> = Vec::new();
+    let mut numbers: Vec<Vec<NumberSpan>> = Vec::new();
+    let mut symbols: HashMap<Position, char> = HashMap::new();
+
+    for (i, line) in input_lines(file).enumerate() {
+        let (tokens, line_width) = tokenize_line(line, i)?;
+        let mut row_numbers: Vec<NumberSpan> = Vec::new();
+
+        for token in tokens {
+            match token {
+                Token::Number {
+                    column,
+                    width,
+                    value,
+                } => row_numbers.push(NumberSpan {
+                    column,
+                    width,
+                    value,
+                }),
+                Token::Symbol { column, symbol } => {
+                    symbols.insert(Position { row: i, column }, symbol);
+                }
+            }
+        }
+
+        row_numbers.shrink_to_fit();
+        numbers.push(row_numbers);
+        widths.push(line_width);
+    }
+
+    let row_widths = resolve_row_widths(&widths, ragged_rows)?;
+    symbols.shrink_to_fit();
+
+    Ok(SparseSchematic {
+        row_widths,
+        numbers,
+        symbols,
+    })
+}
+
+fn is_part_number(
+    schematic: &impl Grid,
+    position: &Position,
+    width: usize,
+    neighbourhood: &Neighbourhood,
+) -> bool {
+    let neighbours = schematic.get_neighbours(position, width, neighbourhood);
+    let mut neighbours = neighbours.iter();
+    neighbours.any(|p| schematic.is_symbol(p))
+}
+
+fn solution1(schematic: &impl Grid, neighbourhood: &Neighbourhood) -> Result<u64, OverflowError> {
+    let numbers = schematic.numbers().into_iter();
+
+    numbers
+        .filter(|(pos, _value, width)| is_part_number(schematic, pos, *width, neighbourhood))
+        .try_fold(0u64, |total, (pos, value, _width)| {
+            total
+                .checked_add(value)
+                .ok_or(OverflowError { position: pos })
         })
-        .sum()
 }
 
-fn solution2(schematic: &Schematic) -> u32 {
-    // Find all gears.
-    let row_iter = schematic.matrix.iter();
+impl Operation {
+    /// `None` if the result overflows.
+    fn apply(&self, numbers: &[u64]) -> Option<u64> {
+        match self {
+            Operation::Sum => numbers.iter().try_fold(0u64, |acc, n| acc.checked_add(*n)),
+            Operation::Product => numbers.iter().try_fold(1u64, |acc, n| acc.checked_mul(*n)),
+            Operation::Max => Some(numbers.iter().copied().max().unwrap_or(0)),
+            Operation::Count => Some(numbers.len() as u64),
+        }
+    }
+}
+
+impl Arity {
+    fn allows(&self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == *n,
+            Arity::AtLeast(n) => count >= *n,
+        }
+    }
+}
+
+impl SymbolRule {
+    /// Parse a rule of the form `symbol:operation:arity`, e.g. `*:product:2` or `+:sum:1+`.
+    fn parse(rule: &str) -> (char, Self) {
+        let symbol = rule
+            .chars()
+            .next()
+            .expect("rule should start with a symbol");
+        let mut rest = rule[symbol.len_utf8()..]
+            .strip_prefix(':')
+            .expect("rule should be symbol:operation:arity")
+            .split(':');
 
-    let gear_pos_iter = row_iter.enumerate().flat_map(|(i, row)| {
-        let col_iter = row
+        let operation = match rest.next() {
+            Some("sum") => Operation::Sum,
+            Some("product") => Operation::Product,
+            Some("max") => Operation::Max,
+            Some("count") => Operation::Count,
+            other => panic!("Unknown operation {:?}", other),
+        };
+
+        let arity = rest.next().expect("rule should have an arity");
+        let arity = match arity.strip_suffix('+') {
+            Some(n) => Arity::AtLeast(n.parse().expect("arity should be a number")),
+            None => Arity::Exactly(arity.parse().expect("arity should be a number")),
+        };
+
+        (symbol, Self { operation, arity })
+    }
+}
+
+/// The value of the rule for the symbol at `position`, or `None` if its arity isn't met.
+fn rule_value(
+    schematic: &impl Grid,
+    position: &Position,
+    rule: &SymbolRule,
+    neighbourhood: &Neighbourhood,
+) -> Result<Option<u64>, OverflowError> {
+    // Collect the part numbers that neighbour the symbol.
+    let neighbours = schematic.get_neighbours(position, 1, neighbourhood);
+    let number_neighbours = neighbours
+        .into_iter()
+        .filter_map(|p| schematic.number_start(&p));
+
+    // Filter out duplicates
+    let number_neighbours: HashSet<Position> = number_neighbours.collect();
+
+    if !rule.arity.allows(number_neighbours.len()) {
+        return Ok(None);
+    }
+
+    let numbers: Vec<u64> = number_neighbours
+        .iter()
+        .map(|p| schematic.get_number(p))
+        .collect();
+    match rule.operation.apply(&numbers) {
+        Some(value) => Ok(Some(value)),
+        None => Err(OverflowError {
+            position: position.clone(),
+        }),
+    }
+}
+
+fn evaluate_rules(
+    schematic: &impl Grid,
+    neighbourhood: &Neighbourhood,
+    rules: &RuleTable,
+) -> Result<u64, OverflowError> {
+    // Find all symbols that have a rule.
+    let symbol_iter = schematic
+        .symbols()
+        .into_iter()
+        .filter_map(|(p, c)| Some((p, rules.get(&c)?)));
+
+    let mut total = 0u64;
+    for (p, rule) in symbol_iter {
+        if let Some(value) = rule_value(schematic, &p, rule, neighbourhood)? {
+            total = total
+                .checked_add(value)
+                .ok_or(OverflowError { position: p })?;
+        }
+    }
+
+    Ok(total)
+}
+
+impl GraphNode {
+    fn position(&self) -> &Position {
+        match self {
+            GraphNode::Number(p) | GraphNode::Symbol(p) => p,
+        }
+    }
+
+    fn id(&self) -> String {
+        match self {
+            GraphNode::Number(p) => format!("n{}_{}", p.row, p.column),
+            GraphNode::Symbol(p) => format!("s{}_{}", p.row, p.column),
+        }
+    }
+
+    fn label(&self, schematic: &impl Grid) -> String {
+        match self {
+            GraphNode::Number(p) => schematic.get_number(p).to_string(),
+            GraphNode::Symbol(p) => match schematic.symbol_at(p) {
+                Some(c) => c.to_string(),
+                None => panic!(
+                    "Value at position ({}, {}) is not a symbol.",
+                    p.row, p.column
+                ),
+            },
+        }
+    }
+}
+
+impl PartGraph {
+    fn build(schematic: &impl Grid, neighbourhood: &Neighbourhood) -> Self {
+        let mut adjacency: BTreeMap<GraphNode, BTreeSet<GraphNode>> = BTreeMap::new();
+
+        // Every number and symbol is a node, even if it touches nothing.
+        for (pos, _, _) in schematic.numbers() {
+            adjacency.insert(GraphNode::Number(pos), BTreeSet::new());
+        }
+        for (pos, _) in schematic.symbols() {
+            adjacency.insert(GraphNode::Symbol(pos), BTreeSet::new());
+        }
+
+        for (pos, _, width) in schematic.numbers() {
+            let number = GraphNode::Number(pos.clone());
+            let neighbours = schematic.get_neighbours(&pos, width, neighbourhood);
+
+            for neighbour in neighbours.into_iter().filter(|p| schematic.is_symbol(p)) {
+                let symbol = GraphNode::Symbol(neighbour);
+                adjacency.get_mut(&number).unwrap().insert(symbol.clone());
+                adjacency.get_mut(&symbol).unwrap().insert(number.clone());
+            }
+        }
+
+        Self { adjacency }
+    }
+
+    fn degree(&self, node: &GraphNode) -> usize {
+        self.adjacency.get(node).map_or(0, |n| n.len())
+    }
+
+    fn edges(&self) -> Vec<(&GraphNode, &GraphNode)> {
+        // Each edge is stored in both directions, so only take it from the number's side.
+        self.adjacency
             .iter()
-            .enumerate()
-            .filter(|(_j, v)| matches!(v, SchematicValue::Gear))
-            .map(|(j, _v)| Position { row: i, column: j });
-
-        let col_vec: Vec<Position> = col_iter.collect();
-        col_vec
-    });
-
-    // Collect pairs of part numbers that neighbour gears.
-    let gear_neighbour_iter = gear_pos_iter.map(|p| {
-        let neighbours = schematic.get_neighbours(&p, 1);
-        let number_neighbours = neighbours
+            .filter(|(node, _)| matches!(node, GraphNode::Number(_)))
+            .flat_map(|(node, neighbours)| neighbours.iter().map(move |n| (node, n)))
+            .collect()
+    }
+
+    fn components(&self) -> Vec<Vec<&GraphNode>> {
+        let mut visited: HashSet<&GraphNode> = HashSet::new();
+        let mut components = Vec::new();
+
+        for start in self.adjacency.keys() {
+            if !visited.insert(start) {
+                continue;
+            }
+
+            let mut component = Vec::new();
+            let mut queue = VecDeque::from([start]);
+            while let Some(node) = queue.pop_front() {
+                component.push(node);
+                for neighbour in self.adjacency[node].iter() {
+                    if visited.insert(neighbour) {
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+
+            components.push(component);
+        }
+
+        components
+    }
+
+    fn to_dot(&self, schematic: &impl Grid) -> String {
+        let mut dot = String::from("graph schematic {\n");
+
+        for node in self.adjacency.keys() {
+            let shape = match node {
+                GraphNode::Number(_) => "box",
+                GraphNode::Symbol(_) => "circle",
+            };
+            dot.push_str(&format!(
+                "    {} [label={:?}, shape={}];\n",
+                node.id(),
+                node.label(schematic),
+                shape
+            ));
+        }
+
+        for (number, symbol) in self.edges() {
+            dot.push_str(&format!("    {} -- {};\n", number.id(), symbol.id()));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_json(&self, schematic: &impl Grid) -> String {
+        let nodes = self
+            .adjacency
+            .keys()
+            .map(|node| JsonNode {
+                id: node.id(),
+                kind: match node {
+                    GraphNode::Number(_) => "number",
+                    GraphNode::Symbol(_) => "symbol",
+                },
+                row: node.position().row,
+                column: node.position().column,
+                label: node.label(schematic),
+                degree: self.degree(node),
+            })
+            .collect();
+
+        let edges = self
+            .edges()
+            .into_iter()
+            .map(|(number, symbol)| (number.id(), symbol.id()))
+            .collect();
+
+        let components = self
+            .components()
             .into_iter()
-            .map(|p| {
-                let val = &schematic.matrix[p.row][p.column];
-                match val {
-                    SchematicValue::NumberContinuation(_, pos) => Some(pos.clone()),
-                    SchematicValue::NumberStart(_, _) => Some(p),
-                    _ => None,
+            .map(|c| c.into_iter().map(|n| n.id()).collect())
+            .collect();
+
+        let graph = JsonGraph {
+            nodes,
+            edges,
+            components,
+        };
+        serde_json::to_string_pretty(&graph).unwrap()
+    }
+}
+
+fn print_components(graph: &PartGraph, schematic: &impl Grid) {
+    for (i, component) in graph.components().into_iter().enumerate() {
+        let nodes: Vec<String> = component
+            .iter()
+            .map(|n| {
+                let pos = n.position();
+                format!(
+                    "{} ({}, {}) degree {}",
+                    n.label(schematic),
+                    pos.row,
+                    pos.column,
+                    graph.degree(n)
+                )
+            })
+            .collect();
+        println!("Component {}: {}", i + 1, nodes.join(", "));
+    }
+}
+
+fn print_query(schematic: &Schematic, position: &Position, neighbourhood: &Neighbourhood) {
+    let query = match schematic.query(position, neighbourhood) {
+        Some(query) => query,
+        None => {
+            println!(
+                "({}, {}) is outside the schematic",
+                position.row, position.column
+            );
+            return;
+        }
+    };
+
+    match query {
+        PointQuery::Blank => println!("({}, {}) is blank", position.row, position.column),
+        PointQuery::Number {
+            start,
+            value,
+            symbols,
+        } => {
+            let kind = if symbols.is_empty() {
+                "not a part number"
+            } else {
+                "a part number"
+            };
+            println!(
+                "({}, {}) is in {} starting at ({}, {}), {}",
+                position.row, position.column, value, start.row, start.column, kind
+            );
+            for (p, c) in symbols {
+                println!("    touches {} at ({}, {})", c, p.row, p.column);
+            }
+        }
+        PointQuery::Symbol { symbol, numbers } => {
+            println!(
+                "({}, {}) is the symbol {}, touching {} numbers",
+                position.row,
+                position.column,
+                symbol,
+                numbers.len()
+            );
+            for (p, n) in numbers {
+                println!("    touches {} at ({}, {})", n, p.row, p.column);
+            }
+        }
+    }
+}
+
+/// Write a `rows` by `columns` schematic that is mostly blank.
+fn generate(filename: &str, rows: usize, columns: usize) {
+    let file = File::create(filename).expect("output file should be writable");
+    let mut writer = BufWriter::new(file);
+
+    // xorshift, so the generated file is the same on every run.
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+
+    for _ in 0..rows {
+        let mut line = String::with_capacity(columns);
+        while line.len() < columns {
+            match next(200) {
+                0 => line.push(['*', '#', '+', '$', '/', '='][next(6) as usize]),
+                1 | 2 => {
+                    let digits = 1 + next(3) as usize;
+                    for _ in 0..std::cmp::min(digits, columns - line.len()) {
+                        line.push(char::from(b'0' + next(10) as u8));
+                    }
+                    // Keep neighbouring numbers from running together.
+                    if line.len() < columns {
+                        line.push('.');
+                    }
+                }
+                _ => line.push('.'),
+            }
+        }
+        writeln!(writer, "{}", line).unwrap();
+    }
+}
+
+fn bench(input: &str, ragged_rows: RaggedRows, neighbourhood: &Neighbourhood) {
+    let start = Instant::now();
+    let schematic = parse_file(input, ragged_rows).unwrap_or_else(|e| panic!("{}", e));
+    let dense_answers = (
+        solution1(&schematic, neighbourhood).unwrap_or_else(|e| panic!("{}", e)),
+        solution2(&schematic, neighbourhood).unwrap_or_else(|e| panic!("{}", e)),
+    );
+    let dense_time = start.elapsed();
+    let dense_size = schematic.heap_size();
+    drop(schematic);
+
+    let start = Instant::now();
+    let sparse = parse_file_sparse(input, ragged_rows).unwrap_or_else(|e| panic!("{}", e));
+    let sparse_answers = (
+        solution1(&sparse, neighbourhood).unwrap_or_else(|e| panic!("{}", e)),
+        solution2(&sparse, neighbourhood).unwrap_or_else(|e| panic!("{}", e)),
+    );
+    let sparse_time = start.elapsed();
+    let sparse_size = sparse.heap_size();
+
+    assert_eq!(
+        dense_answers, sparse_answers,
+        "dense and sparse answers differ"
+    );
+
+    println!("dense: {} bytes, {:?}", dense_size, dense_time);
+    println!("sparse: {} bytes, {:?}", sparse_size, sparse_time);
+    println!(
+        "memory reduction: {:.1}x",
+        dense_size as f64 / sparse_size as f64
+    );
+}
+
+impl Edit {
+    /// Parse `set row,col X`, `insert row,col X` or `delete row,col`.
+    fn parse(command: &str) -> Option<Self> {
+        let mut parts = command.split_whitespace();
+        let name = parts.next()?;
+        let (row, column) = parts.next()?.split_once(',')?;
+        let position = Position {
+            row: row.parse().ok()?,
+            column: column.parse().ok()?,
+        };
+        let symbol = parts.next().and_then(|s| s.chars().next());
+
+        match (name, symbol) {
+            ("set", Some(c)) => Some(Edit::Set(position, c)),
+            ("insert", Some(c)) => Some(Edit::Insert(position, c)),
+            ("delete", None) => Some(Edit::Delete(position)),
+            _ => None,
+        }
+    }
+
+    fn position(&self) -> &Position {
+        match self {
+            Edit::Set(p, _) | Edit::Insert(p, _) | Edit::Delete(p) => p,
+        }
+    }
+}
+
+impl LiveSchematic {
+    fn new(
+        schematic: Schematic,
+        neighbourhood: Neighbourhood,
+        rules: RuleTable,
+    ) -> Result<Self, OverflowError> {
+        let rows: BTreeSet<usize> = (0..schematic.height()).collect();
+        let mut live = Self {
+            schematic,
+            neighbourhood,
+            rules,
+            part_numbers: HashMap::new(),
+            rule_values: HashMap::new(),
+        };
+        live.refresh_rows(&rows)?;
+        Ok(live)
+    }
+
+    /// Rows whose part numbers or rule values can change when `row` is edited.
+    fn affected_rows(&self, row: usize) -> BTreeSet<usize> {
+        let radius = self.neighbourhood.shape.radius() as isize;
+        let height = self.schematic.height() as isize;
+
+        (-radius..=radius)
+            .filter_map(|offset| {
+                let r = row as isize + offset;
+                if self.neighbourhood.wrap {
+                    Some(r.rem_euclid(height) as usize)
+                } else if r >= 0 && r < height {
+                    Some(r as usize)
+                } else {
+                    None
                 }
             })
-            .flatten();
+            .collect()
+    }
+
+    /// Recompute the part numbers and rule values of everything starting in `rows`.
+    /// Nothing is changed if a rule overflows.
+    fn refresh_rows(&mut self, rows: &BTreeSet<usize>) -> Result<(), OverflowError> {
+        let mut part_numbers: Vec<(Position, u64)> = Vec::new();
+        let mut rule_values: Vec<(Position, u64)> = Vec::new();
+
+        for row in rows.iter() {
+            for (pos, value, width) in self.schematic.numbers_in_row(*row) {
+                if is_part_number(&self.schematic, &pos, width, &self.neighbourhood) {
+                    part_numbers.push((pos, value));
+                }
+            }
+
+            for (pos, symbol) in self.schematic.symbols_in_row(*row) {
+                let rule = match self.rules.get(&symbol) {
+                    Some(rule) => rule,
+                    None => continue,
+                };
+                if let Some(value) = rule_value(&self.schematic, &pos, rule, &self.neighbourhood)? {
+                    rule_values.push((pos, value));
+                }
+            }
+        }
+
+        self.part_numbers.retain(|p, _| !rows.contains(&p.row));
+        self.rule_values.retain(|p, _| !rows.contains(&p.row));
+        self.part_numbers.extend(part_numbers);
+        self.rule_values.extend(rule_values);
+
+        Ok(())
+    }
+
+    /// Apply an edit and return the new part number and rule totals. An edit that makes anything
+    /// overflow is undone.
+    fn apply(&mut self, edit: &Edit) -> Result<(u64, u64), EditError> {
+        let position = edit.position();
+        if position.row >= self.schematic.height() {
+            return Err(EditError::OutOfBounds(position.clone()));
+        }
+
+        let old_row = self.schematic.row_chars(position.row);
+        let mut row = old_row.clone();
+        match edit {
+            Edit::Set(p, c) if p.column < row.len() => row[p.column] = *c,
+            Edit::Insert(p, c) if p.column <= row.len() => row.insert(p.column, *c),
+            Edit::Delete(p) if p.column < row.len() => {
+                row.remove(p.column);
+            }
+            _ => return Err(EditError::OutOfBounds(position.clone())),
+        }
+
+        // Re-splitting the row merges or splits any numbers the edit touched.
+        self.schematic
+            .set_row(position.row, &row)
+            .map_err(EditError::Parse)?;
+
+        let rows = self.affected_rows(position.row);
+        if let Err(e) = self.refresh_rows(&rows) {
+            // Put the row back so the schematic still matches the tracked values.
+            self.schematic.set_row(position.row, &old_row).unwrap();
+            return Err(EditError::Overflow(e));
+        }
+
+        match self.totals() {
+            Ok(totals) => Ok(totals),
+            Err(e) => {
+                // The tracked values have already changed, so they need working out again too.
+                self.schematic.set_row(position.row, &old_row).unwrap();
+                self.refresh_rows(&rows)
+                    .expect("values before the edit should not overflow");
+                Err(EditError::Overflow(e))
+            }
+        }
+    }
+
+    fn totals(&self) -> Result<(u64, u64), OverflowError> {
+        Ok((self.part_number_total()?, self.rule_total()?))
+    }
+
+    fn part_number_total(&self) -> Result<u64, OverflowError> {
+        sum_values(&self.part_numbers)
+    }
+
+    fn rule_total(&self) -> Result<u64, OverflowError> {
+        sum_values(&self.rule_values)
+    }
+}
+
+fn sum_values(values: &HashMap<Position, u64>) -> Result<u64, OverflowError> {
+    values.iter().try_fold(0u64, |total, (p, value)| {
+        total.checked_add(*value).ok_or(OverflowError {
+            position: p.clone(),
+        })
+    })
+}
+
+/// Apply edits read from stdin, printing the answers after each one. The symbol rules given
+/// replace the gear rules of part 2.
+fn edit(
+    schematic: Schematic,
+    neighbourhood: Neighbourhood,
+    rules: RuleTable,
+) -> Result<(), OverflowError> {
+    let (rules, rule_label) = if rules.is_empty() {
+        (gear_rules(), "Solution 2")
+    } else {
+        (rules, "Rules")
+    };
+    let mut live = LiveSchematic::new(schematic, neighbourhood, rules)?;
+    live.totals()?;
 
-        // Filter out duplicates
-        let number_neighbours: HashSet<Position> = number_neighbours.collect();
-        number_neighbours
-    });
+    for line in io::stdin().lines() {
+        let line = line.expect("stdin should be readable");
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
 
-    // Filter to gears that have exactly 2 neighbouring part numbers.
-    let gear_neighbour_iter = gear_neighbour_iter.filter(|neighbours| neighbours.len() == 2);
+        if line == "show" {
+            for row in 0..live.schematic.height() {
+                let row: String = live.schematic.row_chars(row).into_iter().collect();
+                println!("{}", row);
+            }
+            continue;
+        }
 
-    // Multiply each pair
-    let gear_ratios = gear_neighbour_iter.map(|neighbours| {
-        let mut neighbours = neighbours.iter();
-        let first = neighbours.next().unwrap();
-        let second = neighbours.next().unwrap();
+        let edit = match Edit::parse(line) {
+            Some(edit) => edit,
+            None => {
+                eprintln!("Unknown edit: {}", line);
+                continue;
+            }
+        };
+
+        match live.apply(&edit) {
+            Ok((part_number_total, rule_total)) => {
+                println!("Solution 1: {}", part_number_total);
+                println!("{}: {}", rule_label, rule_total);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
 
-        let first = schematic.get_number(first);
-        let second = schematic.get_number(second);
+    Ok(())
+}
 
-        first * second
-    });
+fn gear_rules() -> RuleTable {
+    // Gears are stars touching exactly two part numbers, and their ratio is the product.
+    RuleTable::from([(
+        '*',
+        SymbolRule {
+            operation: Operation::Product,
+            arity: Arity::Exactly(2),
+        },
+    )])
+}
 
-    gear_ratios.sum()
+fn solution2(schematic: &impl Grid, neighbourhood: &Neighbourhood) -> Result<u64, OverflowError> {
+    evaluate_rules(schematic, neighbourhood, &gear_rules())
+}
+
+fn print_answers(schematic: &impl Grid, neighbourhood: &Neighbourhood, rules: &RuleTable) {
+    let answers = || -> Result<(), OverflowError> {
+        let answer1 = solution1(schematic, neighbourhood)?;
+        println!("Solution 1: {}", answer1);
+
+        let answer2 = solution2(schematic, neighbourhood)?;
+        println!("Solution 2: {}", answer2);
+
+        if !rules.is_empty() {
+            let rule_answer = evaluate_rules(schematic, neighbourhood, rules)?;
+            println!("Rules: {}", rule_answer);
+        }
+
+        Ok(())
+    };
+
+    if let Err(e) = answers() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
 
 fn main() {
     let mut args = env::args();
     let filename = args.nth(1).expect("Filename must be given.");
+    let flags: Vec<String> = args.collect();
+
+    if let Some(i) = flags.iter().position(|f| f == "--generate") {
+        let size = flags.get(i + 1).expect("--generate should have a value");
+        let (rows, columns) = size.split_once('x').expect("size should be ROWSxCOLUMNS");
+        let rows = rows.parse().expect("rows should be a number");
+        let columns = columns.parse().expect("columns should be a number");
+        generate(&filename, rows, columns);
+        return;
+    }
+
     let input = read_to_string(filename).unwrap();
 
-    let schematic = parse_file(&input);
+    let ragged_rows = if flags.iter().any(|f| f == "--pad") {
+        RaggedRows::Pad
+    } else {
+        RaggedRows::Error
+    };
+
+    let neighbourhood = match flags.iter().position(|f| f == "--neighbourhood") {
+        Some(i) => flags
+            .get(i + 1)
+            .expect("--neighbourhood should have a value"),
+        None => "8",
+    };
+    let wrap = flags.iter().any(|f| f == "--wrap");
+    let neighbourhood = Neighbourhood::parse(neighbourhood, wrap);
+
+    if flags.iter().any(|f| f == "--bench") {
+        bench(&input, ragged_rows, &neighbourhood);
+        return;
+    }
 
-    let answer1 = solution1(&schematic);
-    println!("Solution 1: {}", answer1);
+    let rules: RuleTable = flags
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| *f == "--rule")
+        .map(|(i, _)| SymbolRule::parse(flags.get(i + 1).expect("--rule should have a value")))
+        .collect();
+
+    if flags.iter().any(|f| f == "--sparse") {
+        let schematic = match parse_file_sparse(&input, ragged_rows) {
+            Ok(schematic) => schematic,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+        print_answers(&schematic, &neighbourhood, &rules);
+        return;
+    }
+
+    let schematic = match parse_file(&input, ragged_rows) {
+        Ok(schematic) => schematic,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if flags.iter().any(|f| f == "--edit") {
+        if let Err(e) = edit(schematic, neighbourhood, rules) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(i) = flags.iter().position(|f| f == "--at") {
+        let at = flags.get(i + 1).expect("--at should have a value");
+        let (row, column) = at.split_once(',').expect("--at should be row,col");
+        let position = Position {
+            row: row.trim().parse().expect("row should be a number"),
+            column: column.trim().parse().expect("column should be a number"),
+        };
+        print_query(&schematic, &position, &neighbourhood);
+        return;
+    }
+
+    if let Some(i) = flags.iter().position(|f| f == "--graph") {
+        let graph = PartGraph::build(&schematic, &neighbourhood);
+        match flags.get(i + 1).map(|f| f.as_str()) {
+            Some("dot") => print!("{}", graph.to_dot(&schematic)),
+            Some("json") => println!("{}", graph.to_json(&schematic)),
+            Some("components") => print_components(&graph, &schematic),
+            other => panic!("Unknown graph format {:?}", other),
+        }
+        return;
+    }
 
-    let answer2 = solution2(&schematic);
-    println!("Solution 2: {}", answer2);
+    print_answers(&schematic, &neighbourhood, &rules);
 }
@@ -1,20 +1,271 @@
-use std::collections::HashSet;
+use num_bigint::BigUint;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fs::read_to_string;
+use std::fmt;
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
+use std::process;
+use std::time::Instant;
+
+/// Card numbers are usually below this, so a set of them fits in a fixed-width bitset.
+const NUMBER_LIMIT: u32 = 128;
+
+/// A set of card numbers, one bit per number.
+#[derive(Debug, Clone)]
+enum NumberSet {
+    Fixed([u64; (NUMBER_LIMIT / 64) as usize]),
+    // Once a number reaches `NUMBER_LIMIT`, sized to fit the largest number.
+    Wide(Vec<u64>),
+}
 
 struct Card {
+    id: u32,
+    // How many of your numbers are winning numbers, counted once when the card is parsed.
+    matches: u32,
+}
+
+/// How repeated numbers on a card are counted.
+#[derive(Debug, Clone, Copy)]
+enum Counting {
+    // Each number counts once, however often it appears.
+    Set,
+    // Each of your numbers that is a winning number is a separate match, so a repeated one
+    // matches again. Repeated winning numbers are still only one winning number.
+    Multiset,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NumberList {
+    Winning,
+    Yours,
+}
+
+/// A number that appears more than once in one of a card's lists.
+#[derive(Debug)]
+struct Repeat {
+    card: u32,
+    list: NumberList,
+    number: u32,
+    // Columns in the line, counting from 1.
+    column: usize,
+    first_column: usize,
+}
+
+/// The original card representation, kept to benchmark the bitset against.
+struct HashSetCard {
     winning_numbers: HashSet<u32>,
     your_numbers: HashSet<u32>,
 }
 
-impl Card {
-    fn points(&self) -> u32 {
-        let num_winning: u32 = self.num_matches().try_into().unwrap();
-        if num_winning > 0 {
-            2u32.pow(num_winning - 1)
-        } else {
-            0
+/// A number of card copies, which moves to a big integer once it no longer fits in a `u128`.
+#[derive(Debug, Clone)]
+enum CardCount {
+    Small(u128),
+    Big(BigUint),
+}
+
+/// How many points a card scores for its matches.
+#[derive(Debug, Clone, Copy)]
+enum ScoringRule {
+    // 1, 2, 4, 8, ...
+    Doubling,
+    // 1, 2, 3, 4, ...
+    Linear,
+    // 1, 1, 2, 3, 5, ...
+    Fibonacci,
+    // 1, 4, 9, 16, ...
+    Squared,
+}
+
+/// What happens to copies won of cards past the last card.
+#[derive(Debug, Clone, Copy)]
+enum OverflowPolicy {
+    // Stop with an error.
+    Error,
+    // Only win copies up to the last card.
+    Clamp,
+    // Carry on from the first card. Those cards have already been scored, so their extra copies
+    // don't win any more cards.
+    Wrap,
+}
+
+/// A card's points, or the total so far with them added, don't fit in a `u64`.
+#[derive(Debug)]
+struct PointsOverflow {
+    card: u32,
+}
+
+/// A card won a copy of a card past the last one.
+#[derive(Debug)]
+struct CopyOverflow {
+    card: u32,
+    won: u32,
+}
+
+/// Copies of card `to` won by the copies of card `from`.
+#[derive(Debug)]
+struct Contribution {
+    from: u32,
+    to: u32,
+    copies: CardCount,
+}
+
+/// How copies propagated through the cards.
+#[derive(Debug)]
+struct CopyTrace {
+    card_counts: BTreeMap<u32, CardCount>,
+    // In the order the copies were won, so grouped by the contributing card.
+    contributions: Vec<Contribution>,
+    // Following each copy back through the cards that won it ends at one original card. This
+    // counts the copies that end at each original card.
+    descendants: BTreeMap<u32, CardCount>,
+}
+
+#[derive(Debug)]
+enum IdProblem {
+    // Cards with IDs strictly between these two are missing.
+    Gap { after: u32, before: u32 },
+    Duplicate(u32),
+    OutOfOrder { id: u32, previous: u32 },
+}
+
+impl Default for NumberSet {
+    fn default() -> Self {
+        NumberSet::Fixed([0; (NUMBER_LIMIT / 64) as usize])
+    }
+}
+
+impl NumberSet {
+    fn words(&self) -> &[u64] {
+        match self {
+            NumberSet::Fixed(words) => words,
+            NumberSet::Wide(words) => words,
+        }
+    }
+
+    fn insert(&mut self, number: u32) {
+        let word = (number / 64) as usize;
+        if word >= self.words().len() {
+            let mut words = self.words().to_vec();
+            words.resize(word + 1, 0);
+            *self = NumberSet::Wide(words);
         }
+
+        let words = match self {
+            NumberSet::Fixed(words) => &mut words[..],
+            NumberSet::Wide(words) => &mut words[..],
+        };
+        words[word] |= 1 << (number % 64);
+    }
+
+    fn contains(&self, number: u32) -> bool {
+        self.words()
+            .get((number / 64) as usize)
+            .is_some_and(|w| w & (1 << (number % 64)) != 0)
+    }
+
+    fn intersection_len(&self, other: &NumberSet) -> u32 {
+        // Words past the end of the shorter set have no numbers in common.
+        self.words()
+            .iter()
+            .zip(other.words().iter())
+            .map(|(a, b)| (a & b).count_ones())
+            .sum()
+    }
+}
+
+impl FromIterator<u32> for NumberSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(numbers: I) -> Self {
+        let mut set = NumberSet::default();
+        for n in numbers {
+            set.insert(n);
+        }
+        set
+    }
+}
+
+impl ScoringRule {
+    fn parse(rule: &str) -> Self {
+        match rule {
+            "doubling" => ScoringRule::Doubling,
+            "linear" => ScoringRule::Linear,
+            "fibonacci" => ScoringRule::Fibonacci,
+            "squared" => ScoringRule::Squared,
+            _ => panic!("Unknown scoring rule {}", rule),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ScoringRule::Doubling => "doubling",
+            ScoringRule::Linear => "linear",
+            ScoringRule::Fibonacci => "fibonacci",
+            ScoringRule::Squared => "squared",
+        }
+    }
+
+    /// Points for a card with `matches` matches, or `None` if they don't fit in a `u64`.
+    /// A card without any matches is worth nothing.
+    fn points(&self, matches: u32) -> Option<u64> {
+        if matches == 0 {
+            return Some(0);
+        }
+
+        let matches = u64::from(matches);
+        match self {
+            ScoringRule::Doubling => 2u64.checked_pow((matches - 1) as u32),
+            ScoringRule::Linear => Some(matches),
+            ScoringRule::Fibonacci => {
+                let (mut a, mut b): (u64, u64) = (1, 1);
+                for _ in 1..matches {
+                    (a, b) = (b, a.checked_add(b)?);
+                }
+                Some(a)
+            }
+            ScoringRule::Squared => matches.checked_mul(matches),
+        }
+    }
+}
+
+impl NumberList {
+    fn name(&self) -> &'static str {
+        match self {
+            NumberList::Winning => "winning number",
+            NumberList::Yours => "your number",
+        }
+    }
+}
+
+impl Card {
+    fn new(
+        id: u32,
+        winning_numbers: &[(usize, u32)],
+        your_numbers: &[(usize, u32)],
+        counting: Counting,
+    ) -> Self {
+        let winning_set: NumberSet = winning_numbers.iter().map(|(_, n)| *n).collect();
+        let matches = match counting {
+            Counting::Set => {
+                let your_set: NumberSet = your_numbers.iter().map(|(_, n)| *n).collect();
+                winning_set.intersection_len(&your_set)
+            }
+            Counting::Multiset => your_numbers
+                .iter()
+                .filter(|(_, n)| winning_set.contains(*n))
+                .count() as u32,
+        };
+
+        Card { id, matches }
+    }
+
+    fn points(&self, rule: ScoringRule) -> Option<u64> {
+        rule.points(self.matches)
+    }
+}
+
+impl HashSetCard {
+    fn points(&self, rule: ScoringRule) -> Option<u64> {
+        rule.points(self.num_matches() as u32)
     }
 
     fn num_matches(&self) -> usize {
@@ -23,66 +274,734 @@ impl Card {
     }
 }
 
-fn parse_input(filename: &str) -> Vec<Card> {
+/// Parse a list of numbers starting `offset` bytes into the line, along with the column of each.
+fn parse_numbers(numbers: &str, offset: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+    let mut start = offset;
+    numbers.split(' ').filter_map(move |n| {
+        let column = start + 1;
+        start += n.len() + 1;
+        if n.is_empty() {
+            None
+        } else {
+            Some((column, n.trim().parse::<u32>().expect("Invalid number")))
+        }
+    })
+}
+
+/// Split a card line into its ID, winning numbers and your numbers. Each list comes with the
+/// offset it starts at in the line.
+fn parse_line(l: &str) -> (u32, (usize, &str), (usize, &str)) {
+    let colon = l.find(':').expect("Card should have numbers");
+    let id = l[..colon]
+        .split_whitespace()
+        .last()
+        .and_then(|id| id.parse::<u32>().ok())
+        .expect("Invalid card ID");
+
+    let bar = l.find('|').expect("Card should have your numbers");
+    let winning_numbers = (colon + 1, &l[colon + 1..bar]);
+    let your_numbers = (bar + 1, &l[bar + 1..]);
+
+    (id, winning_numbers, your_numbers)
+}
+
+fn find_repeats(card: u32, list: NumberList, numbers: &[(usize, u32)]) -> Vec<Repeat> {
+    let mut seen = NumberSet::default();
+    let mut repeats = Vec::new();
+
+    for &(column, number) in numbers {
+        if seen.contains(number) {
+            let first_column = numbers.iter().find(|(_, n)| *n == number).unwrap().0;
+            repeats.push(Repeat {
+                card,
+                list,
+                number,
+                column,
+                first_column,
+            });
+        }
+        seen.insert(number);
+    }
+
+    repeats
+}
+
+fn parse_input(filename: &str, counting: Counting) -> (Vec<Card>, Vec<Repeat>) {
+    let lines = read_to_string(filename).unwrap();
+    let lines = lines.lines();
+
+    let mut cards = Vec::new();
+    let mut repeats = Vec::new();
+    // Reused for every line to save allocating two lists per card.
+    let mut winning_numbers: Vec<(usize, u32)> = Vec::new();
+    let mut your_numbers: Vec<(usize, u32)> = Vec::new();
+
+    for l in lines.filter(|l| !l.trim().is_empty()) {
+        let (id, (winning_offset, winning), (your_offset, yours)) = parse_line(l);
+        winning_numbers.clear();
+        winning_numbers.extend(parse_numbers(winning, winning_offset));
+        your_numbers.clear();
+        your_numbers.extend(parse_numbers(yours, your_offset));
+
+        repeats.extend(find_repeats(id, NumberList::Winning, &winning_numbers));
+        repeats.extend(find_repeats(id, NumberList::Yours, &your_numbers));
+        cards.push(Card::new(id, &winning_numbers, &your_numbers, counting));
+    }
+
+    (cards, repeats)
+}
+
+/// Collect numbers into a `HashSet`, noting any that are already in it as `find_repeats` does.
+fn collect_hashset(
+    card: u32,
+    list: NumberList,
+    numbers: impl Iterator<Item = (usize, u32)>,
+    repeats: &mut Vec<Repeat>,
+) -> HashSet<u32> {
+    let mut set = HashSet::new();
+    let mut first_columns: HashMap<u32, usize> = HashMap::new();
+
+    for (column, number) in numbers {
+        let first_column = *first_columns.entry(number).or_insert(column);
+        if !set.insert(number) {
+            repeats.push(Repeat {
+                card,
+                list,
+                number,
+                column,
+                first_column,
+            });
+        }
+    }
+
+    set
+}
+
+fn parse_input_hashset(filename: &str) -> (Vec<HashSetCard>, Vec<Repeat>) {
     let lines = read_to_string(filename).unwrap();
     let lines = lines.lines();
 
-    let cards = lines.map(|l| {
-        let l = l.split(':').nth(1).unwrap();
-        let mut split = l.split('|');
-
-        let winning_numbers = split.next().unwrap();
-        let winning_numbers = winning_numbers
-            .split(' ')
-            .filter(|n| n != &"")
-            .map(|n| n.trim().parse::<u32>().expect("Invalid number"));
-        let winning_numbers: HashSet<u32> = winning_numbers.collect();
-
-        let your_numbers = split.next().unwrap();
-        let your_numbers = your_numbers
-            .split(' ')
-            .filter(|n| n != &"")
-            .map(|n| n.trim().parse::<u32>().expect("Invalid number"));
-        let your_numbers: HashSet<u32> = your_numbers.collect();
-
-        Card {
+    let mut cards = Vec::new();
+    let mut repeats = Vec::new();
+
+    for l in lines.filter(|l| !l.trim().is_empty()) {
+        let (id, (winning_offset, winning_numbers), (your_offset, your_numbers)) = parse_line(l);
+        let winning_numbers = collect_hashset(
+            id,
+            NumberList::Winning,
+            parse_numbers(winning_numbers, winning_offset),
+            &mut repeats,
+        );
+        let your_numbers = collect_hashset(
+            id,
+            NumberList::Yours,
+            parse_numbers(your_numbers, your_offset),
+            &mut repeats,
+        );
+
+        cards.push(HashSetCard {
             winning_numbers,
             your_numbers,
+        });
+    }
+
+    (cards, repeats)
+}
+
+fn check_ids(cards: &[Card]) -> Vec<IdProblem> {
+    let mut problems = Vec::new();
+
+    for (previous, card) in cards.iter().zip(cards.iter().skip(1)) {
+        if card.id < previous.id {
+            problems.push(IdProblem::OutOfOrder {
+                id: card.id,
+                previous: previous.id,
+            });
         }
-    });
+    }
+
+    let mut ids: Vec<u32> = cards.iter().map(|c| c.id).collect();
+    ids.sort();
+
+    for pair in ids.windows(2) {
+        if pair[0] == pair[1] {
+            problems.push(IdProblem::Duplicate(pair[0]));
+        } else if pair[1] > pair[0] + 1 {
+            problems.push(IdProblem::Gap {
+                after: pair[0],
+                before: pair[1],
+            });
+        }
+    }
+
+    problems
+}
+
+fn solution1(cards: &[Card], rule: ScoringRule) -> Result<u64, PointsOverflow> {
+    cards.iter().try_fold(0u64, |total, card| {
+        card.points(rule)
+            .and_then(|points| total.checked_add(points))
+            .ok_or(PointsOverflow { card: card.id })
+    })
+}
+
+impl CardCount {
+    fn to_big(&self) -> BigUint {
+        match self {
+            CardCount::Small(n) => BigUint::from(*n),
+            CardCount::Big(n) => n.clone(),
+        }
+    }
+
+    fn add(&self, other: &CardCount) -> CardCount {
+        match (self, other) {
+            (CardCount::Small(a), CardCount::Small(b)) => match a.checked_add(*b) {
+                Some(n) => CardCount::Small(n),
+                None => CardCount::Big(BigUint::from(*a) + *b),
+            },
+            _ => CardCount::Big(self.to_big() + other.to_big()),
+        }
+    }
+}
 
-    cards.collect()
+impl fmt::Display for CardCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardCount::Small(n) => write!(f, "{}", n),
+            CardCount::Big(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl OverflowPolicy {
+    fn parse(policy: &str) -> Self {
+        match policy {
+            "error" => OverflowPolicy::Error,
+            "clamp" => OverflowPolicy::Clamp,
+            "wrap" => OverflowPolicy::Wrap,
+            _ => panic!("Unknown overflow policy {}", policy),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            OverflowPolicy::Error => "error",
+            OverflowPolicy::Clamp => "clamp",
+            OverflowPolicy::Wrap => "wrap",
+        }
+    }
 }
 
-fn solution1(cards: &[Card]) -> u32 {
-    cards.iter().map(|c| c.points()).sum()
+/// The IDs of the cards that `card` wins a copy of, including IDs in gaps.
+fn won_ids(
+    card: &Card,
+    first_id: u32,
+    last_id: u32,
+    overflow: OverflowPolicy,
+) -> Result<Vec<u32>, CopyOverflow> {
+    let mut won = Vec::new();
+
+    for j in 1..=card.matches {
+        let mut won_id = card.id + j;
+        if won_id > last_id {
+            match overflow {
+                OverflowPolicy::Error => {
+                    return Err(CopyOverflow {
+                        card: card.id,
+                        won: won_id,
+                    })
+                }
+                OverflowPolicy::Clamp => break,
+                OverflowPolicy::Wrap => {
+                    won_id = first_id + (won_id - first_id) % (last_id - first_id + 1)
+                }
+            }
+        }
+        won.push(won_id);
+    }
+
+    Ok(won)
 }
 
-fn solution2(cards: &[Card]) -> u32 {
-    let mut card_counts: Vec<u32> = cards.iter().map(|_| 1).collect();
+/// Count the copies of every card, calling `on_copy` with the card, the ID of the card it won
+/// and how many copies of that card it won each time one is won.
+fn count_copies(
+    cards: &[Card],
+    overflow: OverflowPolicy,
+    mut on_copy: impl FnMut(&Card, u32, &CardCount),
+) -> Result<BTreeMap<u32, CardCount>, CopyOverflow> {
+    // Cards win copies of the cards with the following IDs, so go through them in ID order.
+    let mut card_counts: BTreeMap<u32, CardCount> =
+        cards.iter().map(|c| (c.id, CardCount::Small(1))).collect();
+    let mut cards: Vec<&Card> = cards.iter().collect();
+    cards.sort_by_key(|c| c.id);
 
-    for (i, card) in cards.iter().enumerate() {
-        let card_count = card_counts[i];
-        let matches = card.num_matches();
+    let first_id = cards.first().map_or(0, |c| c.id);
+    let last_id = cards.last().map_or(0, |c| c.id);
 
-        for j in 1..=matches {
-            let j: usize = j;
-            card_counts[i + j] += card_count;
+    for card in cards {
+        let card_count = card_counts[&card.id].clone();
+
+        for won_id in won_ids(card, first_id, last_id, overflow)? {
+            // Missing cards in a gap can't be copied.
+            if let Some(won_count) = card_counts.get_mut(&won_id) {
+                *won_count = won_count.add(&card_count);
+                on_copy(card, won_id, &card_count);
+            }
         }
     }
 
-    card_counts.iter().sum()
+    Ok(card_counts)
+}
+
+fn trace_copies(cards: &[Card], overflow: OverflowPolicy) -> Result<CopyTrace, CopyOverflow> {
+    let mut won: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    let mut contributions = Vec::new();
+
+    let card_counts = count_copies(cards, overflow, |card, won_id, copies| {
+        won.entry(card.id).or_default().push(won_id);
+        contributions.push(Contribution {
+            from: card.id,
+            to: won_id,
+            copies: copies.clone(),
+        });
+    })?;
+
+    // Every copy of a card wins the same copies as the original, so each card has the same
+    // number of descendants wherever it appears. Work backwards so the won cards are done first.
+    let mut descendants: BTreeMap<u32, CardCount> = BTreeMap::new();
+    for id in card_counts.keys().rev() {
+        let count = won
+            .get(id)
+            .into_iter()
+            .flatten()
+            .fold(CardCount::Small(0), |total, won_id| {
+                let total = total.add(&CardCount::Small(1));
+                // Wrapped copies are of cards that have already been scored, so they stop here.
+                match descendants.get(won_id) {
+                    Some(d) if won_id > id => total.add(d),
+                    _ => total,
+                }
+            });
+        descendants.insert(*id, count);
+    }
+
+    Ok(CopyTrace {
+        card_counts,
+        contributions,
+        descendants,
+    })
+}
+
+fn solution2(cards: &[Card], overflow: OverflowPolicy) -> Result<CardCount, CopyOverflow> {
+    let card_counts = count_copies(cards, overflow, |_, _, _| {})?;
+    let total = card_counts
+        .values()
+        .fold(CardCount::Small(0), |total, count| total.add(count));
+    Ok(total)
+}
+
+impl CopyTrace {
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("card,from,copies\n");
+        for c in self.contributions.iter() {
+            csv.push_str(&format!("{},{},{}\n", c.to, c.from, c.copies));
+        }
+        csv
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph copies {\n");
+
+        for (id, count) in self.card_counts.iter() {
+            dot.push_str(&format!(
+                "    c{} [label=\"Card {}\\n{} total\"];\n",
+                id, id, count
+            ));
+        }
+
+        for c in self.contributions.iter() {
+            dot.push_str(&format!(
+                "    c{} -> c{} [label=\"{}\"];\n",
+                c.from, c.to, c.copies
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The original card with the most copies descended from it.
+    fn most_responsible(&self) -> Option<(u32, &CardCount)> {
+        self.descendants
+            .iter()
+            .max_by_key(|(id, count)| (count.to_big(), std::cmp::Reverse(**id)))
+            .map(|(id, count)| (*id, count))
+    }
+
+    fn print(&self) {
+        for (id, count) in self.card_counts.iter() {
+            let sources: Vec<String> = self
+                .contributions
+                .iter()
+                .filter(|c| c.to == *id)
+                .map(|c| format!("{} from card {}", c.copies, c.from))
+                .collect();
+
+            if sources.is_empty() {
+                println!("Card {}: {} (original only)", id, count);
+            } else {
+                println!("Card {}: {} (original, {})", id, count, sources.join(", "));
+            }
+        }
+    }
+}
+
+/// Write `card_count` cards that each win up to ten copies, never past the last card.
+fn generate(filename: &str, card_count: u32) {
+    let file = File::create(filename).expect("output file should be writable");
+    let mut writer = BufWriter::new(file);
+
+    // xorshift, so the generated file is the same on every run.
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound) as u32
+    };
+
+    for id in 1..=card_count {
+        let matches = std::cmp::min(next(11), card_count - id);
+        let winning_numbers: Vec<String> = (1..=10).map(|n| format!("{:2}", n)).collect();
+
+        // The rest of your numbers miss, and none of them repeat.
+        let mut your_numbers: Vec<u32> = (1..=matches).collect();
+        while your_numbers.len() < 10 {
+            let n = 50 + next(50);
+            if !your_numbers.contains(&n) {
+                your_numbers.push(n);
+            }
+        }
+        let your_numbers: Vec<String> = your_numbers.iter().map(|n| format!("{:2}", n)).collect();
+
+        writeln!(
+            writer,
+            "Card {:4}: {} | {}",
+            id,
+            winning_numbers.join(" "),
+            your_numbers.join(" ")
+        )
+        .unwrap();
+    }
+}
+
+/// Time parsing and scoring with the bitset cards against the `HashSet` ones. Both look for
+/// repeated numbers while parsing.
+/// Part 2 walks the same match counts, so the benchmark stops at the counts it would use.
+fn bench(filename: &str) {
+    let start = Instant::now();
+    let (cards, repeats1) = parse_input_hashset(filename);
+    let points1: Option<u64> = cards.iter().try_fold(0u64, |total, card| {
+        total.checked_add(card.points(ScoringRule::Doubling)?)
+    });
+    let matches1: Vec<u32> = cards.iter().map(|c| c.num_matches() as u32).collect();
+    let hashset = start.elapsed();
+
+    let start = Instant::now();
+    let (cards, repeats2) = parse_input(filename, Counting::Set);
+    let points2 = solution1(&cards, ScoringRule::Doubling).ok();
+    let matches2: Vec<u32> = cards.iter().map(|c| c.matches).collect();
+    let bitset = start.elapsed();
+
+    assert_eq!(points1, points2, "points differ");
+    assert_eq!(matches1, matches2, "match counts differ");
+    assert_eq!(repeats1.len(), repeats2.len(), "repeated numbers differ");
+
+    println!("{} cards", cards.len());
+    println!("HashSet: {:?}", hashset);
+    println!("bitset: {:?}", bitset);
+    println!(
+        "speedup: {:.2}x",
+        hashset.as_secs_f64() / bitset.as_secs_f64()
+    );
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let filename = &args[1];
+    let flags = &args[2..];
 
-    let cards = parse_input(filename);
+    if let Some(i) = flags.iter().position(|f| f == "--generate") {
+        let card_count = flags.get(i + 1).expect("--generate should have a value");
+        generate(
+            filename,
+            card_count.parse().expect("card count should be a number"),
+        );
+        return;
+    }
+
+    if flags.iter().any(|f| f == "--bench") {
+        bench(filename);
+        return;
+    }
+
+    let overflow = match flags.iter().position(|f| f == "--overflow") {
+        Some(i) => OverflowPolicy::parse(flags.get(i + 1).expect("--overflow should have a value")),
+        None => OverflowPolicy::Error,
+    };
+
+    let scoring = match flags.iter().position(|f| f == "--scoring") {
+        Some(i) => ScoringRule::parse(flags.get(i + 1).expect("--scoring should have a value")),
+        None => ScoringRule::Doubling,
+    };
+
+    let counting = if flags.iter().any(|f| f == "--multiset") {
+        Counting::Multiset
+    } else {
+        Counting::Set
+    };
+
+    let (cards, repeats) = parse_input(filename, counting);
+    for r in repeats.iter() {
+        eprintln!(
+            "Card {} repeats {} {} at column {} (first at column {})",
+            r.card,
+            r.list.name(),
+            r.number,
+            r.column,
+            r.first_column
+        );
+    }
+
+    let problems = check_ids(&cards);
+    for problem in problems.iter() {
+        match problem {
+            IdProblem::Gap { after, before } => {
+                eprintln!("Cards between {} and {} are missing", after, before)
+            }
+            IdProblem::Duplicate(id) => eprintln!("Card {} appears more than once", id),
+            IdProblem::OutOfOrder { id, previous } => {
+                eprintln!("Card {} comes after card {}", id, previous)
+            }
+        }
+    }
+
+    // Won copies are looked up by ID, which is ambiguous if an ID is repeated.
+    if problems
+        .iter()
+        .any(|p| matches!(p, IdProblem::Duplicate(_)))
+    {
+        process::exit(1);
+    }
+
+    if let Some(i) = flags.iter().position(|f| f == "--trace") {
+        let trace = match trace_copies(&cards, overflow) {
+            Ok(trace) => trace,
+            Err(e) => {
+                eprintln!(
+                    "Card {} won a copy of card {}, which is past the last card",
+                    e.card, e.won
+                );
+                process::exit(1);
+            }
+        };
+
+        match flags.get(i + 1).map(|f| f.as_str()) {
+            Some("text") => trace.print(),
+            Some("csv") => print!("{}", trace.to_csv()),
+            Some("dot") => print!("{}", trace.to_dot()),
+            other => panic!("Unknown trace format {:?}", other),
+        }
+
+        // Keep the exports clean for piping by putting the summary on stderr.
+        if let Some((id, count)) = trace.most_responsible() {
+            eprintln!("Card {} is responsible for the most copies: {}", id, count);
+        }
+        return;
+    }
+
+    match solution1(&cards, scoring) {
+        Ok(answer1) => println!("Solution 1 ({} scoring): {}", scoring.name(), answer1),
+        Err(e) => {
+            eprintln!(
+                "Points overflow at card {} with {} scoring",
+                e.card,
+                scoring.name()
+            );
+            process::exit(1);
+        }
+    }
+
+    match solution2(&cards, overflow) {
+        Ok(answer2) => println!("Solution 2 ({} on overflow): {}", overflow.name(), answer2),
+        Err(e) => {
+            eprintln!(
+                "Card {} won a copy of card {}, which is past the last card",
+                e.card, e.won
+            );
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cards(matches: &[u32]) -> Vec<Card> {
+        matches
+            .iter()
+            .enumerate()
+            .map(|(i, &matches)| Card {
+                id: i as u32 + 1,
+                matches,
+            })
+            .collect()
+    }
 
-    let answer1 = solution1(&cards);
-    println!("Solution 1: {}", answer1);
+    #[test]
+    fn won_ids_past_the_last_card() {
+        let card = Card { id: 6, matches: 2 };
 
-    let answer2 = solution2(&cards);
-    println!("Solution 2: {}", answer2);
+        let error = won_ids(&card, 1, 6, OverflowPolicy::Error).unwrap_err();
+        assert_eq!((error.card, error.won), (6, 7));
+
+        assert!(won_ids(&card, 1, 6, OverflowPolicy::Clamp)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            won_ids(&card, 1, 6, OverflowPolicy::Wrap).unwrap(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn won_ids_wrap_more_than_every_card() {
+        let card = Card { id: 1, matches: 7 };
+        assert_eq!(
+            won_ids(&card, 1, 3, OverflowPolicy::Wrap).unwrap(),
+            vec![2, 3, 1, 2, 3, 1, 2]
+        );
+    }
+
+    #[test]
+    fn number_set_past_the_fixed_width() {
+        let small: NumberSet = [3, 41].into_iter().collect();
+        let wide: NumberSet = [3, 200, 1000].into_iter().collect();
+
+        assert!(matches!(small, NumberSet::Fixed(_)));
+        assert!(wide.contains(200) && wide.contains(1000) && !wide.contains(999));
+        assert_eq!(small.intersection_len(&wide), 1);
+        assert_eq!(wide.intersection_len(&wide), 3);
+    }
+
+    #[test]
+    fn scoring_more_than_32_matches() {
+        let cards = cards(&[40]);
+        assert_eq!(solution1(&cards, ScoringRule::Doubling).unwrap(), 1 << 39);
+        assert_eq!(solution1(&cards, ScoringRule::Linear).unwrap(), 40);
+        assert_eq!(
+            solution1(&cards, ScoringRule::Fibonacci).unwrap(),
+            102_334_155
+        );
+        assert_eq!(solution1(&cards, ScoringRule::Squared).unwrap(), 1600);
+    }
+
+    #[test]
+    fn scoring_overflow_names_the_card() {
+        // 2^64 doesn't fit, and nor does the 100th Fibonacci number.
+        let error = solution1(&cards(&[1, 65]), ScoringRule::Doubling).unwrap_err();
+        assert_eq!(error.card, 2);
+        let error = solution1(&cards(&[100]), ScoringRule::Fibonacci).unwrap_err();
+        assert_eq!(error.card, 1);
+
+        // Each card fits on its own but the total doesn't.
+        let error = solution1(&cards(&[64, 64]), ScoringRule::Doubling).unwrap_err();
+        assert_eq!(error.card, 2);
+    }
+
+    #[test]
+    fn repeated_numbers() {
+        let (set_cards, repeats) = parse_input("test_repeats.txt", Counting::Set);
+        let (multiset_cards, _) = parse_input("test_repeats.txt", Counting::Multiset);
+        let (_, hashset_repeats) = parse_input_hashset("test_repeats.txt");
+
+        for repeats in [&repeats, &hashset_repeats] {
+            assert_eq!(repeats.len(), 2);
+
+            let winning = &repeats[0];
+            assert!(matches!(winning.list, NumberList::Winning));
+            assert_eq!(
+                (
+                    winning.card,
+                    winning.number,
+                    winning.column,
+                    winning.first_column
+                ),
+                (1, 41, 24, 9)
+            );
+
+            let yours = &repeats[1];
+            assert!(matches!(yours.list, NumberList::Yours));
+            assert_eq!(
+                (yours.card, yours.number, yours.column, yours.first_column),
+                (1, 17, 53, 41)
+            );
+        }
+
+        // Card 1 has your 17 twice, which only counts twice as a multiset.
+        assert_eq!(set_cards[0].matches, 4);
+        assert_eq!(multiset_cards[0].matches, 5);
+    }
+
+    #[test]
+    fn solution2_past_u128() {
+        // Each card wins the next two, so copies grow like the Fibonacci numbers and pass
+        // `u128::MAX` after about 185 cards.
+        let card_count = 200;
+        let matches: Vec<u32> = (1..=card_count)
+            .map(|id| (card_count - id).min(2))
+            .collect();
+
+        let mut copies = vec![BigUint::from(1u32); card_count as usize];
+        for i in 0..copies.len() {
+            for j in 1..=matches[i] as usize {
+                copies[i + j] = &copies[i + j] + &copies[i];
+            }
+        }
+        let expected: BigUint = copies.iter().sum();
+        assert!(expected > BigUint::from(u128::MAX));
+
+        let total = solution2(&cards(&matches), OverflowPolicy::Error).unwrap();
+        assert!(matches!(total, CardCount::Big(_)));
+        assert_eq!(total.to_big(), expected);
+    }
+
+    #[test]
+    fn solution2_overflow_policies() {
+        let (cards, _) = parse_input("test_overflow.txt", Counting::Set);
+
+        assert!(solution2(&cards, OverflowPolicy::Error).is_err());
+        assert_eq!(
+            solution2(&cards, OverflowPolicy::Clamp)
+                .unwrap()
+                .to_string(),
+            "30"
+        );
+        assert_eq!(
+            solution2(&cards, OverflowPolicy::Wrap).unwrap().to_string(),
+            "32"
+        );
+    }
+
+    #[test]
+    fn solution2_wrap_more_than_every_card() {
+        // Card 1 wins 2, 3, 1, 2, 3. Its own extra copy has already been scored.
+        let cards = cards(&[5, 0, 0]);
+        assert_eq!(
+            solution2(&cards, OverflowPolicy::Wrap).unwrap().to_string(),
+            "8"
+        );
+    }
 }
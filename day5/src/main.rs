@@ -1,5 +1,11 @@
 use itertools::Itertools;
-use std::{collections::HashMap, env, fs::read_to_string, ops::Range};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env, fmt,
+    fs::read_to_string,
+    ops::Range,
+    process,
+};
 
 #[derive(Debug)]
 struct RangeMap {
@@ -19,6 +25,27 @@ struct CategoryMaps {
     category_map: HashMap<String, HashMap<String, Map>>,
 }
 
+/// Which way a map is followed. Going in reverse maps destination values back to sources.
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// One map on the way from one category to another.
+#[derive(Debug)]
+struct Step<'a> {
+    map: &'a Map,
+    direction: Direction,
+}
+
+/// No chain of maps, followed either way, joins the two categories.
+#[derive(Debug)]
+struct NoPath {
+    from: String,
+    to: String,
+}
+
 impl RangeMap {
     fn new(dest_start: u32, source_start: u32, length: u32) -> Self {
         Self {
@@ -28,22 +55,34 @@ impl RangeMap {
         }
     }
 
-    fn source_in_range(&self, val: u32) -> bool {
-        val >= self.source_start && val - self.source_start < self.length
+    /// The start of the values mapped from and the start of the values mapped to.
+    fn starts(&self, direction: Direction) -> (u32, u32) {
+        match direction {
+            Direction::Forward => (self.source_start, self.dest_start),
+            Direction::Reverse => (self.dest_start, self.source_start),
+        }
+    }
+
+    fn in_range(&self, val: u32, direction: Direction) -> bool {
+        let (from_start, _) = self.starts(direction);
+        val >= from_start && val - from_start < self.length
     }
 
-    fn dest_in_range(&self, val: u32) -> bool {
-        val >= self.dest_start && val - self.dest_start < self.length
+    fn input_range(&self, direction: Direction) -> Range<u64> {
+        let (from_start, _) = self.starts(direction);
+        let start = u64::from(from_start);
+        start..start + u64::from(self.length)
     }
 
-    fn map_value(&self, source: u32) -> u32 {
-        let offset = source - self.source_start;
-        self.dest_start + offset
+    fn map_value(&self, val: u32, direction: Direction) -> u32 {
+        let (from_start, to_start) = self.starts(direction);
+        to_start + (val - from_start)
     }
 
-    fn reverse_map_value(&self, dest: u32) -> u32 {
-        let offset = dest - self.dest_start;
-        self.source_start + offset
+    fn map_range(&self, range: Range<u64>, direction: Direction) -> Range<u64> {
+        let (from_start, to_start) = self.starts(direction);
+        let start = u64::from(to_start) + (range.start - u64::from(from_start));
+        start..start + (range.end - range.start)
     }
 }
 
@@ -58,22 +97,54 @@ impl Map {
         self.range_maps.push(range);
     }
 
-    fn get(&self, key: u32) -> u32 {
+    fn get(&self, key: u32, direction: Direction) -> u32 {
         self.range_maps
             .iter()
-            .filter(|r| r.source_in_range(key))
-            .map(|r| r.map_value(key))
+            .filter(|r| r.in_range(key, direction))
+            .map(|r| r.map_value(key, direction))
             .next()
             .unwrap_or(key)
     }
 
-    fn get_reverse(&self, key: u32) -> u32 {
-        self.range_maps
-            .iter()
-            .filter(|r| r.dest_in_range(key))
-            .map(|r| r.reverse_map_value(key))
-            .next()
-            .unwrap_or(key)
+    /// Map each range to the ranges its values map to. A range is split wherever it crosses the
+    /// edge of a `RangeMap`, and the parts not covered by any pass through unchanged.
+    /// The `RangeMap`s are assumed not to overlap.
+    fn get_ranges(&self, ranges: &[Range<u64>], direction: Direction) -> Vec<Range<u64>> {
+        let mut range_maps: Vec<&RangeMap> = self.range_maps.iter().collect();
+        range_maps.sort_by_key(|r| r.starts(direction).0);
+
+        let mut mapped = Vec::new();
+
+        for range in ranges {
+            let mut start = range.start;
+
+            for range_map in range_maps.iter() {
+                let from = range_map.input_range(direction);
+                if from.end <= start {
+                    continue;
+                }
+                if from.start >= range.end {
+                    break;
+                }
+
+                // The gap before this map.
+                if start < from.start {
+                    mapped.push(start..from.start);
+                    start = from.start;
+                }
+
+                let end = std::cmp::min(range.end, from.end);
+                mapped.push(range_map.map_range(start..end, direction));
+                start = end;
+            }
+
+            // Whatever is left after the last map.
+            if start < range.end {
+                mapped.push(start..range.end);
+            }
+        }
+
+        mapped
     }
 }
 
@@ -83,14 +154,6 @@ impl CategoryMaps {
         Self { category_map }
     }
 
-    fn get_map(&self, from_category: &str, to_category: &str) -> &Map {
-        self.category_map
-            .get(&from_category.to_string())
-            .expect("source category should be mapped")
-            .get(&to_category.to_string())
-            .expect("destination category should be mapped")
-    }
-
     fn add_map(&mut self, from_category: &str, to_category: &str, map: Map) {
         let from_category = from_category.to_string();
         if !self.category_map.contains_key(&from_category) {
@@ -102,115 +165,101 @@ impl CategoryMaps {
         outer_map.insert(to_category.to_string(), map);
     }
 
-    fn get_location(&self, layer_name: &str, value: u32) -> u32 {
-        match layer_name {
-            "seed" => self.seed_to_location(value),
-            "soil" => self.soil_to_location(value),
-            "fertilizer" => self.fertilizer_to_location(value),
-            "water" => self.water_to_location(value),
-            "light" => self.light_to_location(value),
-            "temperature" => self.temperature_to_location(value),
-            "humidity" => self.humidity_to_location(value),
-            _ => panic!("Unknown layer: {}", layer_name),
-        }
-    }
-
-    fn get_seed(&self, layer_name: &str, value: u32) -> u32 {
-        match layer_name {
-            "soil" => self.soil_to_seed(value),
-            "fertilizer" => self.fertilizer_to_seed(value),
-            "water" => self.water_to_seed(value),
-            "light" => self.light_to_seed(value),
-            "temperature" => self.temperature_to_seed(value),
-            "humidity" => self.humidity_to_seed(value),
-            "location" => self.location_to_seed(value),
-            _ => panic!("Unknown layer: {}", layer_name),
-        }
-    }
-
-    fn seed_to_location(&self, seed: u32) -> u32 {
-        // seed -> soil
-        let seed_to_soil = self.get_map("seed", "soil");
-        let soil = seed_to_soil.get(seed);
-        self.soil_to_location(soil)
-    }
+    /// The shortest chain of maps written from one category to the next, as the maps along it.
+    /// Categories are tried in name order so the chain doesn't depend on hash order.
+    fn forward_path(&self, from: &str, to: &str) -> Option<Vec<&Map>> {
+        let mut previous: HashMap<&str, (&str, &Map)> = HashMap::new();
+        let mut visited: HashSet<&str> = HashSet::from([from]);
+        let mut queue: VecDeque<&str> = VecDeque::from([from]);
 
-    fn soil_to_location(&self, soil: u32) -> u32 {
-        let soil_to_fertilizer = self.get_map("soil", "fertilizer");
-        let fertilizer = soil_to_fertilizer.get(soil);
-        self.fertilizer_to_location(fertilizer)
-    }
-
-    fn fertilizer_to_location(&self, fertilizer: u32) -> u32 {
-        // fertilizer -> water
-        let fertilizer_to_water = self.get_map("fertilizer", "water");
-        let water = fertilizer_to_water.get(fertilizer);
-        self.water_to_location(water)
-    }
-
-    fn water_to_location(&self, water: u32) -> u32 {
-        let water_to_light = self.get_map("water", "light");
-        let light = water_to_light.get(water);
-        self.light_to_location(light)
-    }
+        while let Some(category) = queue.pop_front() {
+            if category == to {
+                break;
+            }
 
-    fn light_to_location(&self, light: u32) -> u32 {
-        let light_to_temperature = self.get_map("light", "temperature");
-        let temperature = light_to_temperature.get(light);
-        self.temperature_to_location(temperature)
-    }
+            let mut maps: Vec<(&String, &Map)> = self
+                .category_map
+                .get(category)
+                .into_iter()
+                .flatten()
+                .collect();
+            maps.sort_by_key(|(next, _)| *next);
+
+            for (next, map) in maps {
+                if visited.insert(next) {
+                    previous.insert(next, (category, map));
+                    queue.push_back(next);
+                }
+            }
+        }
 
-    fn temperature_to_location(&self, temperature: u32) -> u32 {
-        let temperature_to_humidity = self.get_map("temperature", "humidity");
-        let humidity = temperature_to_humidity.get(temperature);
-        self.humidity_to_location(humidity)
-    }
+        if !visited.contains(to) {
+            return None;
+        }
 
-    fn humidity_to_location(&self, humidity: u32) -> u32 {
-        let humidity_to_location = self.get_map("humidity", "location");
-        humidity_to_location.get(humidity)
-    }
+        let mut maps = Vec::new();
+        let mut category = to;
+        while let Some((before, map)) = previous.remove(category) {
+            maps.push(map);
+            category = before;
+        }
+        maps.reverse();
 
-    // Reverse mapping
-    fn soil_to_seed(&self, soil: u32) -> u32 {
-        let seed_to_soil = self.get_map("seed", "soil");
-        seed_to_soil.get_reverse(soil)
+        Some(maps)
     }
 
-    fn fertilizer_to_seed(&self, ferilizer: u32) -> u32 {
-        let soil_to_fertilizer = self.get_map("soil", "fertilizer");
-        let soil = soil_to_fertilizer.get_reverse(ferilizer);
-        self.soil_to_seed(soil)
-    }
+    /// The maps to follow from one category to another. Following a map backwards isn't an exact
+    /// inverse, so the maps are either all followed forwards or, failing that, all backwards along
+    /// a chain from `to` to `from`. A chain that mixes the two is never used.
+    fn path(&self, from: &str, to: &str) -> Result<Vec<Step<'_>>, NoPath> {
+        if let Some(maps) = self.forward_path(from, to) {
+            let steps = maps.into_iter().map(|map| Step {
+                map,
+                direction: Direction::Forward,
+            });
+            return Ok(steps.collect());
+        }
 
-    fn water_to_seed(&self, water: u32) -> u32 {
-        let fertilizer_to_water = self.get_map("fertilizer", "water");
-        let fertilizer = fertilizer_to_water.get_reverse(water);
-        self.fertilizer_to_seed(fertilizer)
-    }
+        if let Some(maps) = self.forward_path(to, from) {
+            let steps = maps.into_iter().rev().map(|map| Step {
+                map,
+                direction: Direction::Reverse,
+            });
+            return Ok(steps.collect());
+        }
 
-    fn light_to_seed(&self, light: u32) -> u32 {
-        let water_to_light = self.get_map("water", "light");
-        let water = water_to_light.get_reverse(light);
-        self.water_to_seed(water)
+        Err(NoPath {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
     }
 
-    fn temperature_to_seed(&self, temperature: u32) -> u32 {
-        let light_to_temperature = self.get_map("light", "temperature");
-        let light = light_to_temperature.get_reverse(temperature);
-        self.light_to_seed(light)
+    fn convert(&self, from: &str, to: &str, value: u32) -> Result<u32, NoPath> {
+        let path = self.path(from, to)?;
+        let value = path
+            .iter()
+            .fold(value, |value, step| step.map.get(value, step.direction));
+        Ok(value)
     }
 
-    fn humidity_to_seed(&self, humidity: u32) -> u32 {
-        let temperature_to_humidity = self.get_map("temperature", "humidity");
-        let temperature = temperature_to_humidity.get_reverse(humidity);
-        self.temperature_to_seed(temperature)
+    /// Map ranges of one category to every range of the other they end up in.
+    fn convert_ranges(
+        &self,
+        from: &str,
+        to: &str,
+        ranges: &[Range<u64>],
+    ) -> Result<Vec<Range<u64>>, NoPath> {
+        let path = self.path(from, to)?;
+        let ranges = path.iter().fold(ranges.to_vec(), |ranges, step| {
+            step.map.get_ranges(&ranges, step.direction)
+        });
+        Ok(ranges)
     }
+}
 
-    fn location_to_seed(&self, location: u32) -> u32 {
-        let humidity_to_location = self.get_map("humidity", "location");
-        let humidity = humidity_to_location.get_reverse(location);
-        self.humidity_to_seed(humidity)
+impl fmt::Display for NoPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No maps lead from {} to {}", self.from, self.to)
     }
 }
 
@@ -265,21 +314,22 @@ fn parse_input(filename: &str) -> (Vec<u32>, CategoryMaps) {
     (seeds.collect(), category_maps)
 }
 
-fn solution1(seeds: &[u32], category_maps: &CategoryMaps) -> u32 {
-    seeds
+fn solution1(seeds: &[u32], category_maps: &CategoryMaps) -> Result<u32, NoPath> {
+    let locations: Vec<u32> = seeds
         .iter()
-        .map(|seed| category_maps.seed_to_location(*seed))
-        .min()
-        .unwrap()
+        .map(|seed| category_maps.convert("seed", "location", *seed))
+        .collect::<Result<_, _>>()?;
+
+    Ok(locations.into_iter().min().unwrap())
 }
 
-fn solution2(seeds: &[u32], category_maps: &CategoryMaps) -> u32 {
+fn solution2(seeds: &[u32], category_maps: &CategoryMaps) -> Result<u32, NoPath> {
     // Convert list of seeds to ranges of seeds
-    let seed_ranges: Vec<Range<u32>> = seeds
+    let seed_ranges: Vec<Range<u64>> = seeds
         .chunks(2)
         .map(|seed_pair| {
-            let start = seed_pair[0];
-            let length = seed_pair[1];
+            let start = u64::from(seed_pair[0]);
+            let length = u64::from(seed_pair[1]);
 
             Range {
                 start,
@@ -288,43 +338,17 @@ fn solution2(seeds: &[u32], category_maps: &CategoryMaps) -> u32 {
         })
         .collect();
 
-    // First look at the start of each seed range,
-    let first_minimum = seed_ranges
+    // Every seed ends up in one of these ranges, and every value in them is reached by some seed,
+    // so the lowest location is the lowest start.
+    let location_ranges = category_maps.convert_ranges("seed", "location", &seed_ranges)?;
+    let minimum = location_ranges
         .iter()
-        .map(|r| category_maps.seed_to_location(r.start))
+        .filter(|r| !r.is_empty())
+        .map(|r| r.start)
         .min()
         .unwrap();
 
-    // Go through each layer of the map, taking the bottom of each range.
-    let layers = vec![
-        ("soil", "fertilizer"),
-        ("fertilizer", "water"),
-        ("water", "light"),
-        ("light", "temperature"),
-        ("temperature", "humidity"),
-        ("humidity", "location"),
-    ];
-
-    let min_from_layers = layers
-        .iter()
-        .map(|(from, to)| {
-            let map = category_maps.get_map(from, to);
-            map.range_maps
-                .iter()
-                .filter(|r| {
-                    let seed = category_maps.get_seed(from, r.source_start);
-                    seed_ranges
-                        .iter()
-                        .any(|seed_range| seed_range.contains(&seed))
-                })
-                .map(|r| category_maps.get_location(from, r.source_start))
-                .min()
-                .unwrap_or(u32::MAX)
-        })
-        .min()
-        .unwrap();
-
-    std::cmp::min(first_minimum, min_from_layers)
+    Ok(minimum.try_into().expect("location should fit in a u32"))
 }
 
 fn main() {
@@ -332,9 +356,61 @@ fn main() {
     let filename = args.nth(1).expect("Filename must be given.");
     let (seeds, category_maps) = parse_input(&filename);
 
-    let answer1 = solution1(&seeds, &category_maps);
-    println!("{}", answer1);
+    let answers = solution1(&seeds, &category_maps)
+        .and_then(|answer1| Ok((answer1, solution2(&seeds, &category_maps)?)));
 
-    let answer2 = solution2(&seeds, &category_maps);
-    println!("{}", answer2);
+    match answers {
+        Ok((answer1, answer2)) => {
+            println!("{}", answer1);
+            println!("{}", answer2);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answers(filename: &str) -> (u32, u32) {
+        let (seeds, category_maps) = parse_input(filename);
+        (
+            solution1(&seeds, &category_maps).unwrap(),
+            solution2(&seeds, &category_maps).unwrap(),
+        )
+    }
+
+    #[test]
+    fn extra_and_dead_end_categories() {
+        assert_eq!(answers("test_branching.txt"), (35, 46));
+    }
+
+    #[test]
+    fn reversed_shortcut_is_not_taken() {
+        assert_eq!(answers("test_shortcut.txt"), (35, 46));
+    }
+
+    #[test]
+    fn every_map_reversed() {
+        assert_eq!(answers("test_reversed.txt"), (35, 46));
+    }
+
+    #[test]
+    fn mixed_directions_are_rejected() {
+        let (_, mut category_maps) = parse_input("test.txt");
+        let map = category_maps
+            .category_map
+            .get_mut("soil")
+            .unwrap()
+            .remove("fertilizer")
+            .unwrap();
+        category_maps.add_map("fertilizer", "soil", map);
+
+        // Only a chain that follows the fertilizer-to-soil map backwards joins them now.
+        assert!(category_maps.path("seed", "location").is_err());
+        assert!(category_maps.path("location", "seed").is_err());
+    }
 }